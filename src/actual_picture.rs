@@ -10,6 +10,11 @@ pub struct ActualPicture {
 impl ActualPicture {
     pub fn new(ctx: &mut Context, img: graphics::Image, name: String) -> ActualPicture {
         let raw_bytes = img.to_rgba8(ctx).unwrap();
+        ActualPicture::from_rgba8(&raw_bytes, img.width(), img.height(), name)
+    }
+
+    // for pictures read without a ggez context, as when rendering headless
+    pub fn from_rgba8(raw_bytes: &[u8], w: u16, h: u16, name: String) -> ActualPicture {
         println!("raw len:{}", raw_bytes.len());
         let brightness: Vec<f32> = raw_bytes
             .chunks_exact(4)
@@ -21,8 +26,8 @@ impl ActualPicture {
         println!("brightlen:{}", brightness.len());
         ActualPicture {
             brightness: brightness,
-            w: img.width(),
-            h: img.height(),
+            w: w,
+            h: h,
             name: name,
         }
     }
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+// how far luminance has to jump between neighbours to count as an edge
+const EDGE_THRESHOLD: f32 = 0.1;
+
+/// Cheap measures of how interesting an rgba8 image from Pic::get_rgba8 is
+pub struct ImageStats {
+    /// Variance of luminance, with luminance in 0 to 1
+    pub variance: f32,
+    pub unique_colors: usize,
+    /// Fraction of neighbouring pixels with an edge between them
+    pub edge_density: f32,
+    /// Fraction of pixels that repeat the one before, a solid image is 1
+    /// and pure noise is near 0
+    pub repeat_fraction: f32,
+}
+
+pub struct DegenerateThresholds {
+    pub min_variance: f32,
+    pub min_unique_colors: usize,
+    /// Images past both of these noise limits are thrown out
+    pub noise_edge_density: f32,
+    pub noise_repeat_fraction: f32,
+}
+
+impl Default for DegenerateThresholds {
+    fn default() -> Self {
+        DegenerateThresholds {
+            min_variance: 0.001,
+            min_unique_colors: 2, // mono pictures only ever have 2
+            noise_edge_density: 0.45,
+            noise_repeat_fraction: 0.05,
+        }
+    }
+}
+
+fn luminance(pixel: &[u8]) -> f32 {
+    (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) / 255.0
+}
+
+pub fn analyze(rgba: &[u8], w: usize, h: usize) -> ImageStats {
+    let pixels: Vec<&[u8]> = rgba.chunks_exact(4).collect();
+    let lum: Vec<f32> = pixels.iter().map(|pixel| luminance(pixel)).collect();
+    let count = lum.len() as f32;
+
+    let mean = lum.iter().sum::<f32>() / count;
+    let variance = lum.iter().map(|l| (l - mean) * (l - mean)).sum::<f32>() / count;
+
+    let unique_colors = pixels
+        .iter()
+        .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], 0]))
+        .collect::<HashSet<u32>>()
+        .len();
+
+    let mut edges = 0;
+    let mut pairs = 0;
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            if x + 1 < w {
+                pairs += 1;
+                if (lum[i] - lum[i + 1]).abs() > EDGE_THRESHOLD {
+                    edges += 1;
+                }
+            }
+            if y + 1 < h {
+                pairs += 1;
+                if (lum[i] - lum[i + w]).abs() > EDGE_THRESHOLD {
+                    edges += 1;
+                }
+            }
+        }
+    }
+    let edge_density = if pairs == 0 {
+        0.0
+    } else {
+        edges as f32 / pairs as f32
+    };
+
+    let repeats = pixels
+        .windows(2)
+        .filter(|pair| pair[0][0..3] == pair[1][0..3])
+        .count();
+    let repeat_fraction = repeats as f32 / count;
+
+    ImageStats {
+        variance,
+        unique_colors,
+        edge_density,
+        repeat_fraction,
+    }
+}
+
+impl ImageStats {
+    /// Why the image isn't worth a spot in the grid, or None if it is
+    pub fn degenerate_reason(&self, thresholds: &DegenerateThresholds) -> Option<&'static str> {
+        if self.unique_colors < thresholds.min_unique_colors {
+            Some("solid color")
+        } else if self.variance < thresholds.min_variance {
+            Some("near constant")
+        } else if self.edge_density > thresholds.noise_edge_density
+            && self.repeat_fraction < thresholds.noise_repeat_fraction
+        {
+            Some("pure noise")
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::actual_picture::*;
+use crate::coordinates::*;
+use crate::hdr::*;
+use crate::pic::*;
+use crate::stack_machine::*;
+use rayon::prelude::*;
+use simdeez::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// adaptive antialiasing checks and resamples the image in squares this big
+const TILE_SIZE: usize = 8;
+// the sample grid is turned by atan(1/2) so no two samples share a row or column
+const GRID_ANGLE: f32 = 0.4636476;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Antialias {
+    /// One sample in the middle of each pixel
+    Off,
+    /// n by n samples in every pixel
+    Grid(usize),
+    /// One sample a pixel, then n by n in the tiles where neighbouring pixels
+    /// differ by more than threshold, from 0 to 1, in any channel
+    Adaptive(usize, f32),
+}
+
+/// Where the samples of an n by n grid sit, in pixels from the middle of the pixel
+pub fn sample_offsets(n: usize) -> Vec<(f32, f32)> {
+    let (sin, cos) = GRID_ANGLE.sin_cos();
+    let mut offsets = Vec::with_capacity(n * n);
+    for j in 0..n {
+        for i in 0..n {
+            let x = (i as f32 + 0.5) / n as f32 - 0.5;
+            let y = (j as f32 + 0.5) / n as f32 - 0.5;
+            offsets.push((x * cos - y * sin, x * sin + y * cos));
+        }
+    }
+    offsets
+}
+
+/// Averages n by n renders of pic in linear light into result
+pub fn render_grid<S: Simd, C: Channel>(
+    pic: &Pic,
+    threaded: bool,
+    pics: Arc<HashMap<String, ActualPicture>>,
+    w: usize,
+    h: usize,
+    viewport: &Viewport,
+    n: usize,
+    time: Time,
+    result: &mut [C],
+) {
+    render_window::<S, C>(
+        pic,
+        threaded,
+        pics,
+        (w, h),
+        viewport,
+        (0, 0, w, h),
+        n,
+        time,
+        result,
+    );
+}
+
+/// Renders once, then renders the tiles that look aliased again with n by n samples
+pub fn render_adaptive<S: Simd, C: Channel>(
+    pic: &Pic,
+    threaded: bool,
+    pics: Arc<HashMap<String, ActualPicture>>,
+    w: usize,
+    h: usize,
+    viewport: &Viewport,
+    n: usize,
+    threshold: f32,
+    time: Time,
+    result: &mut [C],
+) {
+    pic.render_single::<S, C>(threaded, pics.clone(), w, h, viewport, time, result);
+    let mut tiles = Vec::new();
+    for (y0, th) in spans(h) {
+        for (x0, tw) in spans(w) {
+            if max_difference(result, w, h, (x0, y0, tw, th)) > threshold {
+                tiles.push((x0, y0, tw, th));
+            }
+        }
+    }
+    let render_tile = |tile: &(usize, usize, usize, usize)| {
+        let mut pixels = vec![C::default(); tile.2 * tile.3 * 4];
+        let pics = pics.clone();
+        render_window::<S, C>(
+            pic,
+            false,
+            pics,
+            (w, h),
+            viewport,
+            *tile,
+            n,
+            time,
+            &mut pixels,
+        );
+        pixels
+    };
+    let rendered: Vec<Vec<C>> = if threaded {
+        tiles.par_iter().map(render_tile).collect()
+    } else {
+        tiles.iter().map(render_tile).collect()
+    };
+    for ((x0, y0, tw, th), pixels) in tiles.iter().zip(rendered.iter()) {
+        for row in 0..*th {
+            let start = ((y0 + row) * w + x0) * 4;
+            result[start..start + tw * 4]
+                .copy_from_slice(&pixels[row * tw * 4..(row + 1) * tw * 4]);
+        }
+    }
+}
+
+// Renders the (x0, y0, tw, th) part of a w by h image n by n times and
+// averages the renders in linear light into result, which holds just that part
+fn render_window<S: Simd, C: Channel>(
+    pic: &Pic,
+    threaded: bool,
+    pics: Arc<HashMap<String, ActualPicture>>,
+    (w, h): (usize, usize),
+    viewport: &Viewport,
+    (x0, y0, tw, th): (usize, usize, usize, usize),
+    n: usize,
+    time: Time,
+    result: &mut [C],
+) {
+    let offsets = sample_offsets(n.max(1));
+    let mut sum = vec![0.0f32; tw * th * 3];
+    let mut sample = vec![C::default(); tw * th * 4];
+    for (ox, oy) in &offsets {
+        let window = viewport.window(w, h, (x0, y0, tw, th), *ox, *oy);
+        let pics = pics.clone();
+        pic.render_single::<S, C>(threaded, pics, tw, th, &window, time, &mut sample);
+        for (total, pixel) in sum.chunks_exact_mut(3).zip(sample.chunks_exact(4)) {
+            for c in 0..3 {
+                total[c] += pixel[c].to_linear();
+            }
+        }
+    }
+    let count = offsets.len() as f32;
+    for (pixel, total) in result.chunks_exact_mut(4).zip(sum.chunks_exact(3)) {
+        for c in 0..3 {
+            pixel[c] = C::from_linear(total[c] / count);
+        }
+        pixel[3] = C::from_unit(1.0);
+    }
+}
+
+// Splits len pixels into runs of TILE_SIZE, never leaving a run of 1 at the
+// end since a window one pixel wide has no width to step across
+fn spans(len: usize) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let mut size = TILE_SIZE.min(len - start);
+        if len - start - size == 1 {
+            size += 1;
+        }
+        spans.push((start, size));
+        start += size;
+    }
+    spans
+}
+
+// The biggest channel difference, from 0 to 1, between a pixel of the tile and
+// the pixels next to it, which can be just outside the tile
+fn max_difference<C: Channel>(
+    rgba: &[C],
+    w: usize,
+    h: usize,
+    (x0, y0, tw, th): (usize, usize, usize, usize),
+) -> f32 {
+    let mut max = 0.0f32;
+    for y in y0..y0 + th {
+        for x in x0..x0 + tw {
+            let i = (y * w + x) * 4;
+            let mut neighbours = Vec::with_capacity(4);
+            if x > 0 {
+                neighbours.push(i - 4);
+            }
+            if x + 1 < w {
+                neighbours.push(i + 4);
+            }
+            if y > 0 {
+                neighbours.push(i - w * 4);
+            }
+            if y + 1 < h {
+                neighbours.push(i + w * 4);
+            }
+            for j in neighbours {
+                for c in 0..3 {
+                    max = max.max((rgba[i + c].to_unit() - rgba[j + c].to_unit()).abs());
+                }
+            }
+        }
+    }
+    max
+}
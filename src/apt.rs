@@ -1,101 +1,47 @@
+use crate::generation::*;
+use crate::noise::*;
+use crate::operator::*;
 use crate::parser::*;
+use crate::pic::*;
 use rand::prelude::*;
 use std::sync::mpsc::*;
-use variant_count::*;
 use APTNode::*;
 
-#[derive(VariantCount, Clone)]
+// deepest subtree mutate will graft into a tree
+const MUTATION_DEPTH: usize = 4;
+
+#[derive(Clone)]
 pub enum APTNode {
-    Add(Vec<APTNode>),
-    Sub(Vec<APTNode>),
-    Mul(Vec<APTNode>),
-    Div(Vec<APTNode>),
-    FBM(Vec<APTNode>),
-    Ridge(Vec<APTNode>),
-    Turbulence(Vec<APTNode>),
-    Cell1(Vec<APTNode>),
-    Cell2(Vec<APTNode>),
-    Sqrt(Vec<APTNode>),
-    Sin(Vec<APTNode>),
-    Atan(Vec<APTNode>),
-    Atan2(Vec<APTNode>),
-    Tan(Vec<APTNode>),
-    Log(Vec<APTNode>),
-    Abs(Vec<APTNode>),
-    Floor(Vec<APTNode>),
-    Ceil(Vec<APTNode>),
-    Clamp(Vec<APTNode>),
-    Wrap(Vec<APTNode>),
-    Square(Vec<APTNode>),
-    Max(Vec<APTNode>),
-    Min(Vec<APTNode>),
-    Mod(Vec<APTNode>),
-    Mandlebrot(Vec<APTNode>),
+    Op(Operator, Vec<APTNode>),
+    /// A noise operator along with the settings for its noise field
+    Noise(Operator, NoiseParams, Vec<APTNode>),
     Picture(String, Vec<APTNode>),
     Constant(f32),
     X,
     Y,
     T,
+    /// The second half of looping time, see Time
+    T2,
     Empty,
 }
 
 impl APTNode {
     pub fn to_lisp(&self) -> String {
         match self {
-            Add(children) => format!("( + {} {} )", children[0].to_lisp(), children[1].to_lisp()),
-            Sub(children) => format!("( - {} {} )", children[0].to_lisp(), children[1].to_lisp()),
-            Mul(children) => format!("( * {} {} )", children[0].to_lisp(), children[1].to_lisp()),
-            Div(children) => format!("( / {} {} )", children[0].to_lisp(), children[1].to_lisp()),
-            FBM(children) => format!(
-                "( FBM {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp()
-            ),
-            Ridge(children) => format!(
-                "( Ridge {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp()
-            ),
-            Cell1(children) => format!(
-                "( Cell1 {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp()
-            ),
-            Cell2(children) => format!(
-                "( Cell2 {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp()
-            ),
-            Turbulence(children) => format!(
-                "( Turbulence {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp()
-            ),
-            Sqrt(children) => format!("( Sqrt {} )", children[0].to_lisp()),
-            Sin(children) => format!("( Sin {} )", children[0].to_lisp()),
-            Atan(children) => format!("( Atan {} )", children[0].to_lisp()),
-            Atan2(children) => format!(
-                "( Atan2 {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp()
-            ),
-            Tan(children) => format!("( Tan {} )", children[0].to_lisp()),
-            Log(children) => format!("( Log {} )", children[0].to_lisp()),
-            Abs(children) => format!("( Abs {} )", children[0].to_lisp()),
-            Floor(children) => format!("( Floor {} )", children[0].to_lisp()),
-            Ceil(children) => format!("( Ceil {} )", children[0].to_lisp()),
-            Clamp(children) => format!("( Clamp  {} )", children[0].to_lisp()),
-            Wrap(children) => format!("( Wrap {} )", children[0].to_lisp()),
-            Square(children) => format!("( Square {} )", children[0].to_lisp()),
-            Max(children) => format!("( Max {} {})", children[0].to_lisp(), children[1].to_lisp()),
-            Min(children) => format!("( Min {} {})", children[0].to_lisp(), children[1].to_lisp()),
-            Mod(children) => format!("( Mod {} {})", children[0].to_lisp(), children[1].to_lisp()),
-            Mandlebrot(children) => format!("( Mandlebrot {} {})",children[0].to_lisp(), children[1].to_lisp()),
+            Op(op, children) => {
+                let mut lisp = format!("( {}", op.name());
+                for child in children {
+                    lisp += &format!(" {}", child.to_lisp());
+                }
+                lisp + " )"
+            }
+            Noise(op, params, children) => {
+                let mut lisp = format!("( {}", op.name());
+                for child in children {
+                    lisp += &format!(" {}", child.to_lisp());
+                }
+                lisp + &format!(" {} )", params.to_lisp(*op))
+            }
             Picture(name, children) => format!(
                 "( Pic-{} {} {} )",
                 name,
@@ -106,100 +52,77 @@ impl APTNode {
             X => format!("X"),
             Y => format!("Y"),
             T => format!("T"),
+            T2 => format!("T2"),
             Empty => format!("EMPTY"),
         }
     }
 
     pub fn str_to_node(s: &str) -> Result<APTNode, String> {
         let lower = &s.to_lowercase()[..];
+        if let Some(op) = Operator::from_name(lower) {
+            if op.noise_kind().is_some() {
+                return Ok(Noise(op, NoiseParams::new(op), vec![Empty; op.arity()]));
+            }
+            return Ok(Op(op, vec![Empty; op.arity()]));
+        }
         match lower {
-            "+" => Ok(Add(vec![Empty, Empty])),
-            "-" => Ok(Sub(vec![Empty, Empty])),
-            "*" => Ok(Mul(vec![Empty, Empty])),
-            "/" => Ok(Div(vec![Empty, Empty])),
-            "fbm" => Ok(FBM(vec![Empty, Empty, Empty])),
-            "ridge" => Ok(Ridge(vec![Empty, Empty, Empty])),
-            "turbulence" => Ok(Turbulence(vec![Empty, Empty, Empty])),
-            "cell1" => Ok(Cell1(vec![Empty, Empty, Empty])),
-            "cell2" => Ok(Cell2(vec![Empty, Empty, Empty])),
-            "sqrt" => Ok(Sqrt(vec![Empty])),
-            "sin" => Ok(Sin(vec![Empty])),
-            "atan" => Ok(Atan(vec![Empty])),
-            "atan2" => Ok(Atan2(vec![Empty, Empty])),
-            "tan" => Ok(Tan(vec![Empty])),
-            "log" => Ok(Log(vec![Empty])),
-            "abs" => Ok(Abs(vec![Empty])),
-            "floor" => Ok(Floor(vec![Empty])),
-            "ceil" => Ok(Ceil(vec![Empty])),
-            "clamp" => Ok(Clamp(vec![Empty])),
-            "wrap" => Ok(Wrap(vec![Empty])),
-            "square" => Ok(Square(vec![Empty])),
-            "max" => Ok(Max(vec![Empty, Empty])),
-            "min" => Ok(Min(vec![Empty, Empty])),
-            "mod" => Ok(Mod(vec![Empty, Empty])),
-            _ if lower == "pic" => Ok(X),
+            _ if lower.starts_with("pic-") => Ok(Picture(s[4..].to_string(), vec![Empty, Empty])),
             "x" => Ok(X),
             "y" => Ok(Y),
             "t" => Ok(T),
+            "t2" => Ok(T2),
             _ => Err(format!("Unknown operation '{}' ", s.to_string())),
         }
     }
 
-    pub fn get_random_node(rng: &mut StdRng, pic_names: &Vec<&String>) -> APTNode {
-        let r = rng.gen_range(0, APTNode::VARIANT_COUNT - 5);
-
-        match r {
-            0 => Add(vec![Empty, Empty]),
-            1 => Sub(vec![Empty, Empty]),
-            2 => Mul(vec![Empty, Empty]),
-            3 => Div(vec![Empty, Empty]),
-            4 => FBM(vec![Empty, Empty, Empty, Empty, Empty, Empty]),
-            5 => Ridge(vec![Empty, Empty, Empty, Empty, Empty, Empty]),
-            6 => Turbulence(vec![Empty, Empty, Empty, Empty, Empty, Empty]),
-            7 => Cell1(vec![Empty, Empty, Empty, Empty, Empty]),
-            8 => Cell2(vec![Empty, Empty, Empty, Empty, Empty]),
-            9 => Sqrt(vec![Empty]),
-            10 => Sin(vec![Empty]),
-            11 => Atan(vec![Empty]),
-            12 => Atan2(vec![Empty, Empty]),
-            13 => Tan(vec![Empty]),
-            14 => Log(vec![Empty]),
-            15 => Abs(vec![Empty]),
-            16 => Floor(vec![Empty]),
-            17 => Ceil(vec![Empty]),
-            18 => Clamp(vec![Empty]),
-            19 => Wrap(vec![Empty]),
-            20 => Square(vec![Empty]),
-            21 => Max(vec![Empty, Empty]),
-            22 => Min(vec![Empty, Empty]),
-            23 => Mod(vec![Empty, Empty]),
-            24 => Mandlebrot(vec![Empty,Empty]),
-            25 => {
-                let r = rng.gen_range(0, pic_names.len()) as usize;
-                Picture(pic_names[r].to_string(), vec![Empty, Empty])
+    pub fn get_random_node(
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        let mut r = rng.gen_range(0, config.node_weight(!pic_names.is_empty(), video));
+
+        for op in OPERATORS.iter().filter(|op| video || !op.uses_time()) {
+            let weight = config.operator_weight(*op);
+            if r < weight {
+                if op.noise_kind().is_some() {
+                    let params = NoiseParams::random(*op, rng);
+                    return Noise(*op, params, vec![Empty; op.arity()]);
+                }
+                return Op(*op, vec![Empty; op.arity()]);
             }
-            _ => panic!("get_random_node generated unhandled r:{}", r),
+            r -= weight;
         }
+        let r = rng.gen_range(0, pic_names.len()) as usize;
+        Picture(pic_names[r].to_string(), vec![Empty, Empty])
     }
 
-    pub fn get_random_leaf(rng: &mut StdRng) -> APTNode {
-        let r = rng.gen_range(0, 3);
-        match r {
-            0 => APTNode::X,
-            1 => APTNode::Y,
-            2 => APTNode::Constant(rng.gen_range(-1.0, 1.0)),
-            _ => panic!("get_random_leaf generated unhandled r:{}", r),
+    pub fn get_random_leaf(rng: &mut StdRng, config: &GenerationConfig) -> APTNode {
+        let leaves = &config.leaves;
+        let r = rng.gen_range(0, config.leaf_weight(false));
+        if r < leaves.x {
+            APTNode::X
+        } else if r < leaves.x + leaves.y {
+            APTNode::Y
+        } else {
+            APTNode::Constant(rng.gen_range(config.constant_min, config.constant_max))
         }
     }
 
-    pub fn get_random_leaf_video(rng: &mut StdRng) -> APTNode {
-        let r = rng.gen_range(0, 4);
-        match r {
-            0 => APTNode::X,
-            1 => APTNode::Y,
-            2 => APTNode::T,
-            3 => APTNode::Constant(rng.gen_range(-1.0, 1.0)),
-            _ => panic!("get_random_leaf generated unhandled r:{}", r),
+    pub fn get_random_leaf_video(rng: &mut StdRng, config: &GenerationConfig) -> APTNode {
+        let leaves = &config.leaves;
+        let r = rng.gen_range(0, config.leaf_weight(true));
+        if r < leaves.x {
+            APTNode::X
+        } else if r < leaves.x + leaves.y {
+            APTNode::Y
+        } else if r < leaves.x + leaves.y + leaves.t {
+            APTNode::T
+        } else if r < leaves.x + leaves.y + leaves.t + leaves.t2 {
+            APTNode::T2
+        } else {
+            APTNode::Constant(rng.gen_range(config.constant_min, config.constant_max))
         }
     }
 
@@ -239,77 +162,15 @@ impl APTNode {
 
     pub fn constant_eval(&self) -> f32 {
         match self {
-            Add(children) => children[0].constant_eval() + children[1].constant_eval(),
-            Sub(children) => children[0].constant_eval() - children[1].constant_eval(),
-            Mul(children) => children[0].constant_eval() * children[1].constant_eval(),
-            Div(children) => children[0].constant_eval() / children[1].constant_eval(),
-            FBM(_) => 0.0,
-            Ridge(_) => 0.0,
-            Turbulence(_) => 0.0, // if the noise functions all have constants it isn't worth bothering maybe?
-            Cell1(_) => 0.0,
-            Cell2(_) => 0.0,
-            Sqrt(children) => children[0].constant_eval().sqrt(),
-            Sin(children) => children[0].constant_eval().sin(),
-            Atan(children) => children[0].constant_eval().atan(),
-            Atan2(children) => children[0]
-                .constant_eval()
-                .atan2(children[1].constant_eval()),
-            Tan(children) => children[0].constant_eval().tan(),
-            Log(children) => children[0].constant_eval().log2(),
-            Abs(children) => children[0].constant_eval().abs(),
-            Floor(children) => children[0].constant_eval().floor(),
-            Ceil(children) => children[0].constant_eval().ceil(),
-            Clamp(children) => {
-                let v = children[0].constant_eval();
-                if v > 1.0 {
-                    1.0
-                } else if v < -1.0 {
-                    -1.0
-                } else {
-                    v
-                }
-            }
-            Wrap(children) => {
-                let v = children[0].constant_eval();
-                if v >= -1.0 && v <= 1.0 {
-                    v
-                } else {
-                    let t = (v + 1.0) / 2.0;
-                    -1.0 + 2.0 * (t - t.floor())
-                }
-            }
-            Square(children) => {
-                let v = children[0].constant_eval();
-                v * v
-            }
-            Max(children) => {
-                let a = children[0].constant_eval();
-                let b = children[1].constant_eval();
-                if a >= b {
-                    a
-                } else {
-                    b
-                }
+            Op(op, children) => {
+                let args: Vec<f32> = children.iter().map(|child| child.constant_eval()).collect();
+                op.scalar(&args[..])
             }
-            Min(children) => {
-                let a = children[0].constant_eval();
-                let b = children[1].constant_eval();
-                if a <= b {
-                    a
-                } else {
-                    b
-                }
-            }
-            Mod(children) => {
-                let a = children[0].constant_eval();
-                let b = children[1].constant_eval();
-                a % b
-            }
-            Mandlebrot(children) => {
-                //todo 
-                0.0
+            Noise(op, params, children) => {
+                let args: Vec<f32> = children.iter().map(|child| child.constant_eval()).collect();
+                noise_scalar(*op, &args[..], params)
             }
-            Picture(name, children) => {
+            Picture(_, _) => {
                 //todo
                 0.0
             }
@@ -320,36 +181,14 @@ impl APTNode {
 
     fn set_children(&self, children: Vec<APTNode>) -> Self {
         match self {
-            Add(_) => Add(children),
-            Sub(_) => Sub(children),
-            Mul(_) => Mul(children),
-            Div(_) => Div(children),
-            FBM(_) => FBM(children),
-            Ridge(_) => Ridge(children),
-            Turbulence(_) => Turbulence(children),
-            Cell1(_) => Cell1(children),
-            Cell2(_) => Cell2(children),
-            Sqrt(_) => Sqrt(children),
-            Sin(_) => Sin(children),
-            Atan(_) => Atan(children),
-            Atan2(_) => Atan(children),
-            Tan(_) => Tan(children),
-            Log(_) => Log(children),
-            Abs(_) => Abs(children),
-            Floor(_) => Floor(children),
-            Ceil(_) => Ceil(children),
-            Clamp(_) => Clamp(children),
-            Wrap(_) => Wrap(children),
-            Square(_) => Square(children),
-            Max(_) => Max(children),
-            Min(_) => Min(children),
-            Mod(_) => Mod(children),
-            Mandlebrot(_) => Mandlebrot(children),
+            Op(op, _) => Op(*op, children),
+            Noise(op, params, _) => Noise(*op, *params, children),
             Picture(name, _) => Picture(name.to_string(), children),
             Constant(v) => Constant(*v),
             X => X,
             Y => Y,
             T => T,
+            T2 => T2,
             Empty => panic!("tried to eval an empty node"),
         }
     }
@@ -360,6 +199,7 @@ impl APTNode {
             X => X,
             Y => Y,
             T => T,
+            T2 => T2,
             _ => {
                 let children = self.get_children().unwrap();
                 //foreach child -> constant_fold(child), if you get back all constants -> compute the new constant, and create it
@@ -384,84 +224,352 @@ impl APTNode {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> APTNode {
         let leaf_func = if video {
             APTNode::get_random_leaf_video
         } else {
             APTNode::get_random_leaf
         };
-        let mut first = APTNode::get_random_node(rng, pic_names);
+        let mut first = APTNode::get_random_node(video, rng, pic_names, config);
         for _ in 1..count {
-            first.add_random(APTNode::get_random_node(rng, pic_names), rng);
+            first.add_random(APTNode::get_random_node(video, rng, pic_names, config), rng);
         }
-        while first.add_leaf(&leaf_func(rng)) {}
+        while first.add_leaf(&leaf_func(rng, config)) {}
         first
     }
 
+    /// Builds a tree with either the node count method above or one of the
+    /// depth based methods, min and max are depths for the latter. The tree is
+    /// pruned to the style's bloat limits like a bred one
+    pub fn generate(
+        min: usize,
+        max: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        let mut tree = match config.method {
+            GenerationMethod::Random => {
+                let count = rng.gen_range(min, max + 1);
+                APTNode::generate_tree(count, video, rng, pic_names, config)
+            }
+            GenerationMethod::Grow => {
+                let depth = rng.gen_range(min, max + 1);
+                APTNode::generate_grow(min, depth, video, rng, pic_names, config)
+            }
+            GenerationMethod::Full => {
+                let depth = rng.gen_range(min, max + 1);
+                APTNode::generate_full(depth, video, rng, pic_names, config)
+            }
+            GenerationMethod::RampedHalfAndHalf => {
+                APTNode::generate_ramped(min, max, video, rng, pic_names, config)
+            }
+        };
+        tree.prune_to_limits(&config.limits, video, rng, config);
+        tree
+    }
+
+    /// Every branch ends at a leaf somewhere between min_depth and max_depth
+    pub fn generate_grow(
+        min_depth: usize,
+        max_depth: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        APTNode::generate_to_depth(min_depth, max_depth, false, video, rng, pic_names, config)
+    }
+
+    /// Every branch is exactly depth long
+    pub fn generate_full(
+        depth: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        APTNode::generate_to_depth(depth, depth, true, video, rng, pic_names, config)
+    }
+
+    /// Picks a depth in the range then builds half the trees with grow and half with full,
+    /// which gives a population a good spread of shapes and sizes
+    pub fn generate_ramped(
+        min_depth: usize,
+        max_depth: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        let depth = rng.gen_range(min_depth, max_depth + 1);
+        if rng.gen::<bool>() {
+            APTNode::generate_grow(min_depth, depth, video, rng, pic_names, config)
+        } else {
+            APTNode::generate_full(depth, video, rng, pic_names, config)
+        }
+    }
+
+    // depths count nodes on the path, so a depth of 1 is a lone leaf
+    fn generate_to_depth(
+        min_depth: usize,
+        max_depth: usize,
+        full: bool,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        let leaf_func = if video {
+            APTNode::get_random_leaf_video
+        } else {
+            APTNode::get_random_leaf
+        };
+        let is_leaf = if max_depth <= 1 {
+            true
+        } else if full || min_depth > 1 {
+            false
+        } else {
+            // there are far more operators than leaves, so picking from both
+            // by weight almost never stops early. Ending a branch with a
+            // chance of one over the depth left spreads them across the range
+            rng.gen_range(0, max_depth) == 0
+        };
+        if is_leaf {
+            return leaf_func(rng, config);
+        }
+
+        let mut node = APTNode::get_random_node(video, rng, pic_names, config);
+        for child in node.get_children_mut().unwrap() {
+            *child = APTNode::generate_to_depth(
+                min_depth.saturating_sub(1),
+                max_depth - 1,
+                full,
+                video,
+                rng,
+                pic_names,
+                config,
+            );
+        }
+        node
+    }
+
+    // walks the tree in pre-order, counting index down to the node we want
+    fn node_at_mut(&mut self, index: &mut usize) -> Option<&mut APTNode> {
+        if *index == 0 {
+            return Some(self);
+        }
+        *index -= 1;
+        match self.get_children_mut() {
+            Some(children) => {
+                for child in children {
+                    if let Some(node) = child.node_at_mut(index) {
+                        return Some(node);
+                    }
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn node_at(&self, index: &mut usize) -> Option<&APTNode> {
+        if *index == 0 {
+            return Some(self);
+        }
+        *index -= 1;
+        match self.get_children() {
+            Some(children) => {
+                for child in children {
+                    if let Some(node) = child.node_at(index) {
+                        return Some(node);
+                    }
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// The node at a pre-order index, 0 is the root
+    pub fn get_node(&self, index: usize) -> &APTNode {
+        self.node_at(&mut index.clone())
+            .expect("node index out of range")
+    }
+
+    pub fn get_node_mut(&mut self, index: usize) -> &mut APTNode {
+        self.node_at_mut(&mut index.clone())
+            .expect("node index out of range")
+    }
+
+    /// Replaces a random subtree of a copy of this tree with a random subtree of other
+    pub fn crossover(&self, other: &APTNode, rng: &mut StdRng) -> APTNode {
+        let mut child = self.clone();
+        let target = rng.gen_range(0, child.size());
+        let donor = rng.gen_range(0, other.size());
+        *child.get_node_mut(target) = other.get_node(donor).clone();
+        child
+    }
+
+    /// Replaces a random subtree of a copy of this tree with a new random subtree,
+    /// or when it lands on a noise node sometimes just changes its settings
+    pub fn mutate(
+        &self,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> APTNode {
+        let mut child = self.clone();
+        let target = rng.gen_range(0, child.size());
+        let node = child.get_node_mut(target);
+        match node {
+            Noise(op, params, _) if rng.gen_range(0, NOISE_MUTATION_CHANCE) == 0 => {
+                *params = params.mutate(*op, rng);
+            }
+            _ => *node = APTNode::generate_grow(1, MUTATION_DEPTH, video, rng, pic_names, config),
+        }
+        child
+    }
+
+    /// Swaps every subtree that reaches past max_depth for a random leaf
+    pub fn prune(
+        &mut self,
+        max_depth: usize,
+        video: bool,
+        rng: &mut StdRng,
+        config: &GenerationConfig,
+    ) {
+        if max_depth <= 1 {
+            if !self.is_leaf() {
+                *self = if video {
+                    APTNode::get_random_leaf_video(rng, config)
+                } else {
+                    APTNode::get_random_leaf(rng, config)
+                };
+            }
+            return;
+        }
+        if let Some(children) = self.get_children_mut() {
+            for child in children {
+                child.prune(max_depth - 1, video, rng, config);
+            }
+        }
+    }
+
+    /// Prunes the tree to the depth limit, then shallower until it also fits
+    /// the size limit
+    pub fn prune_to_limits(
+        &mut self,
+        limits: &BloatLimits,
+        video: bool,
+        rng: &mut StdRng,
+        config: &GenerationConfig,
+    ) {
+        let mut depth = limits.max_depth;
+        self.prune(depth, video, rng, config);
+        while self.size() > limits.max_size && depth > 1 {
+            depth -= 1;
+            self.prune(depth, video, rng, config);
+        }
+    }
+
+    /// Number of nodes in the tree, leaves included
+    pub fn size(&self) -> usize {
+        match self.get_children() {
+            Some(children) => 1 + children.iter().map(|child| child.size()).sum::<usize>(),
+            None => 1,
+        }
+    }
+
+    /// Number of nodes on the longest path from here to a leaf, a lone leaf is 1
+    pub fn depth(&self) -> usize {
+        match self.get_children() {
+            Some(children) => {
+                1 + children
+                    .iter()
+                    .map(|child| child.depth())
+                    .max()
+                    .unwrap_or(0)
+            }
+            None => 1,
+        }
+    }
+
     pub fn get_children_mut(&mut self) -> Option<&mut Vec<APTNode>> {
         match self {
-            Add(children) | Sub(children) | Mul(children) | Div(children) | FBM(children)
-            | Ridge(children) | Turbulence(children) | Cell1(children) | Cell2(children)
-            | Sqrt(children) | Sin(children) | Atan(children) | Atan2(children) | Tan(children)
-            | Log(children) | Abs(children) | Floor(children) | Ceil(children)
-            | Clamp(children) | Wrap(children) | Square(children) | Max(children)
-            | Min(children) | Mod(children) | Mandlebrot(children) => Some(children),         
-            Picture(_, children) => Some(children),
+            Op(_, children) | Noise(_, _, children) | Picture(_, children) => Some(children),
             _ => None,
         }
     }
 
     pub fn get_children(&self) -> Option<&Vec<APTNode>> {
         match self {
-            Add(children) | Sub(children) | Mul(children) | Div(children) | FBM(children)
-            | Ridge(children) | Turbulence(children) | Cell1(children) | Cell2(children)
-            | Sqrt(children) | Sin(children) | Atan(children) | Atan2(children) | Tan(children)
-            | Log(children) | Abs(children) | Floor(children) | Ceil(children)
-            | Clamp(children) | Wrap(children) | Square(children) | Max(children)
-            | Min(children) | Mod(children) | Mandlebrot(children) => Some(children),
-            Picture(_, children) => Some(children),
+            Op(_, children) | Noise(_, _, children) | Picture(_, children) => Some(children),
             _ => None,
         }
     }
 
     pub fn is_leaf(&self) -> bool {
         match self {
-            APTNode::X | APTNode::Y | APTNode::T | APTNode::Constant(_) | APTNode::Empty => true,
+            APTNode::X
+            | APTNode::Y
+            | APTNode::T
+            | APTNode::T2
+            | APTNode::Constant(_)
+            | APTNode::Empty => true,
             _ => false,
         }
     }
 
     pub fn parse_apt_node(receiver: &Receiver<Token>) -> Result<APTNode, String> {
+        // nodes with children are wrapped in parens, their closing parens are
+        // consumed here so whatever comes after the tree can be parsed
+        let mut open_parens = 0;
         loop {
-            match receiver.recv() {
-                Ok(token) => {
-                    match token {
-                        Token::Operation(s, line_num) => {
-                            let mut node = APTNode::str_to_node(s)
-                                .map_err(|msg| msg + &format!(" on line {}", line_num))?;
-                            match node.get_children_mut() {
-                                Some(children) => {
-                                    for child in children {
-                                        *child = APTNode::parse_apt_node(receiver)?;
-                                    }
-                                    return Ok(node);
-                                }
-                                None => return Ok(node),
-                            }
+            let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+            let node = match token {
+                Token::OpenParen(_) => {
+                    open_parens += 1;
+                    continue;
+                }
+                Token::CloseParen(line_num) => {
+                    return Err(format!("Unexpected ')' on line {}", line_num))
+                }
+                Token::Text(text, line_num) => {
+                    return Err(format!("Unexpected \"{}\" on line {}", text, line_num))
+                }
+                Token::Operation(s, line_num) => {
+                    let mut node = APTNode::str_to_node(s)
+                        .map_err(|msg| msg + &format!(" on line {}", line_num))?;
+                    if let Some(children) = node.get_children_mut() {
+                        for child in children {
+                            *child = APTNode::parse_apt_node(receiver)?;
                         }
-                        Token::Constant(vstr, line_num) => {
-                            let v = vstr.parse::<f32>().map_err(|_| {
-                                format!("Unable to parse number {} on line {}", vstr, line_num)
-                            })?;
-                            return Ok(APTNode::Constant(v));
+                    }
+                    // noise settings come after the children and end with
+                    // the node's own close paren
+                    if let Noise(op, params, _) = &mut node {
+                        if open_parens > 0 {
+                            *params = parse_noise_params(*op, receiver)?;
+                            open_parens -= 1;
                         }
-                        _ => (), //parens don't matter
                     }
+                    node
                 }
-                Err(_) => {
-                    return Err("Unexpected end of file".to_string());
+                Token::Constant(vstr, line_num) => {
+                    let v = vstr.parse::<f32>().map_err(|_| {
+                        format!("Unable to parse number {} on line {}", vstr, line_num)
+                    })?;
+                    APTNode::Constant(v)
                 }
+            };
+            for _ in 0..open_parens {
+                expect_close_paren(receiver)?;
             }
+            return Ok(node);
         }
     }
 }
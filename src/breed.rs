@@ -0,0 +1,133 @@
+use crate::apt::*;
+use crate::genealogy::*;
+use crate::generation::*;
+use crate::pic::*;
+use rand::prelude::*;
+
+const TOURNAMENT_SIZE: usize = 3;
+const CROSSOVER_CHANCE: f32 = 0.7; // the rest are mutations
+
+pub fn within_limits(tree: &APTNode, limits: &BloatLimits) -> bool {
+    tree.depth() <= limits.max_depth && tree.size() <= limits.max_size
+}
+
+/// Runs a genetic operation on parent, then holds what it made to the bloat limits
+pub fn limit_bloat<F>(
+    parent: &APTNode,
+    video: bool,
+    rng: &mut StdRng,
+    config: &GenerationConfig,
+    mut operation: F,
+) -> APTNode
+where
+    F: FnMut(&mut StdRng) -> APTNode,
+{
+    let limits = &config.limits;
+    match limits.policy {
+        LimitPolicy::Reject => {
+            let child = operation(rng);
+            if within_limits(&child, limits) {
+                child
+            } else {
+                parent.clone()
+            }
+        }
+        LimitPolicy::Retry(retries) => {
+            for _ in 0..retries + 1 {
+                let child = operation(rng);
+                if within_limits(&child, limits) {
+                    return child;
+                }
+            }
+            parent.clone()
+        }
+        LimitPolicy::Prune => {
+            let mut child = operation(rng);
+            child.prune_to_limits(limits, video, rng, config);
+            child
+        }
+    }
+}
+
+pub fn crossover_tree(
+    a: &APTNode,
+    b: &APTNode,
+    video: bool,
+    rng: &mut StdRng,
+    config: &GenerationConfig,
+) -> APTNode {
+    limit_bloat(a, video, rng, config, |rng| a.crossover(b, rng))
+}
+
+pub fn mutate_tree(
+    a: &APTNode,
+    video: bool,
+    rng: &mut StdRng,
+    pic_names: &Vec<&String>,
+    config: &GenerationConfig,
+) -> APTNode {
+    limit_bloat(a, video, rng, config, |rng| {
+        a.mutate(video, rng, pic_names, config)
+    })
+}
+
+/// Tournament selection on the ratings, returns the index of the winner. With
+/// parsimony on each node costs a little rating, so of two pictures rated about
+/// the same the smaller one wins
+pub fn select_parent(
+    pics: &[Pic],
+    ratings: &[f32],
+    rng: &mut StdRng,
+    config: &GenerationConfig,
+) -> usize {
+    let score = |i: usize| ratings[i] - config.limits.parsimony * pics[i].size() as f32;
+    let mut best = rng.gen_range(0, pics.len());
+    for _ in 1..TOURNAMENT_SIZE {
+        let i = rng.gen_range(0, pics.len());
+        if score(i) > score(best) {
+            best = i;
+        }
+    }
+    best
+}
+
+pub struct Child {
+    pub pic: Pic,
+    /// Indexes into the parents passed to breed_population
+    pub parents: Vec<usize>,
+    pub origin: Origin,
+}
+
+/// Breeds count children from the rated parents. Child i is bred from
+/// individual_seed(seed, i)
+pub fn breed_population(
+    parents: &[Pic],
+    ratings: &[f32],
+    count: usize,
+    seed: u64,
+    video: bool,
+    pic_names: &Vec<&String>,
+    config: &GenerationConfig,
+) -> Vec<Child> {
+    let mut children = Vec::with_capacity(count);
+    for i in 0..count {
+        let rng = &mut StdRng::seed_from_u64(individual_seed(seed, i));
+        let a = select_parent(parents, ratings, rng, config);
+        let child = if rng.gen_range(0.0, 1.0) < CROSSOVER_CHANCE {
+            let b = select_parent(parents, ratings, rng, config);
+            Child {
+                pic: parents[a].crossover(&parents[b], video, rng, config),
+                parents: vec![a, b],
+                origin: Origin::Crossover,
+            }
+        } else {
+            Child {
+                pic: parents[a].mutate(video, rng, pic_names, config),
+                parents: vec![a],
+                origin: Origin::Mutation,
+            }
+        };
+        children.push(child);
+    }
+    children
+}
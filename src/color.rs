@@ -0,0 +1,178 @@
+use crate::ggez_utility::*;
+use ggez::graphics::Color;
+use rand::rngs::StdRng;
+use rand::*;
+use simdeez::*;
+use std::f32::consts::PI;
+
+// OkLab and OkLch trees turn -1 to 1 into a chroma up to this, about the most
+// an sRGB colour reaches
+pub const MAX_CHROMA: f32 = 0.32;
+
+/// The space gradient colours are blended in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Blend {
+    /// Straight between the stored values, midpoints come out dark and muddy
+    Srgb,
+    /// Between amounts of light, as mixing lamps would
+    Linear,
+    /// Perceptually even steps in lightness and colour
+    OkLab,
+    /// Like OkLab but round the hue circle, so midpoints keep their saturation
+    OkLch,
+}
+
+use Blend::*;
+
+impl Blend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Srgb => "Srgb",
+            Linear => "Linear",
+            OkLab => "OkLab",
+            OkLch => "OkLch",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Blend> {
+        match &s.to_lowercase()[..] {
+            "srgb" => Some(Srgb),
+            "linear" => Some(Linear),
+            "oklab" => Some(OkLab),
+            "oklch" => Some(OkLch),
+            _ => None,
+        }
+    }
+
+    /// One of the spaces that blend evenly, new gradients never use Srgb
+    pub fn random(rng: &mut StdRng) -> Blend {
+        match rng.gen_range(0, 3) {
+            0 => Linear,
+            1 => OkLab,
+            2 => OkLch,
+            _ => panic!("invalid"),
+        }
+    }
+
+    /// The colour pct of the way from a to b
+    pub fn mix(self, a: Color, b: Color, pct: f32) -> Color {
+        let lerp = |a: f32, b: f32| a * (1.0 - pct) + b * pct;
+        let alpha = lerp(a.a, b.a);
+        match self {
+            Srgb => lerp_color(a, b, pct),
+            Linear => {
+                let (a, b) = (to_linear(a), to_linear(b));
+                from_linear((lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2)), alpha)
+            }
+            OkLab => {
+                let (a, b) = (linear_to_oklab(to_linear(a)), linear_to_oklab(to_linear(b)));
+                let lab = (lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2));
+                from_linear(oklab_to_linear(lab), alpha)
+            }
+            OkLch => {
+                let (a, b) = (linear_to_oklab(to_linear(a)), linear_to_oklab(to_linear(b)));
+                let (ca, cb) = (a.1.hypot(a.2), b.1.hypot(b.2));
+                let (mut ha, mut hb) = (a.2.atan2(a.1), b.2.atan2(b.1));
+                // grays have no hue of their own, so take the other end's
+                if ca < 1e-4 {
+                    ha = hb;
+                }
+                if cb < 1e-4 {
+                    hb = ha;
+                }
+                // the short way round
+                if hb - ha > PI {
+                    hb -= 2.0 * PI;
+                } else if ha - hb > PI {
+                    hb += 2.0 * PI;
+                }
+                let (c, h) = (lerp(ca, cb), lerp(ha, hb));
+                let lab = (lerp(a.0, b.0), c * h.cos(), c * h.sin());
+                from_linear(oklab_to_linear(lab), alpha)
+            }
+        }
+    }
+}
+
+fn to_linear(c: Color) -> (f32, f32, f32) {
+    (
+        srgb_to_linear(c.r),
+        srgb_to_linear(c.g),
+        srgb_to_linear(c.b),
+    )
+}
+
+// blends of in gamut colours can still land a hair outside it
+fn from_linear((r, g, b): (f32, f32, f32), alpha: f32) -> Color {
+    let encode = |v: f32| linear_to_srgb(v.max(0.0).min(1.0));
+    Color::new(encode(r), encode(g), encode(b), alpha)
+}
+
+/// Linear sRGB to OkLab lightness, green to red and blue to yellow
+pub fn linear_to_oklab((r, g, b): (f32, f32, f32)) -> (f32, f32, f32) {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    (
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    )
+}
+
+pub fn oklab_to_linear((l, a, b): (f32, f32, f32)) -> (f32, f32, f32) {
+    let l_ = l + 0.3963377774 * a + 0.2158037573 * b;
+    let m_ = l - 0.1055613458 * a - 0.0638541728 * b;
+    let s_ = l - 0.0894841775 * a - 1.2914855480 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    (
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    )
+}
+
+/// OkLab to sRGB from 0 to 1. Colours outside the sRGB gamut come out past 0
+/// or 1 for the renderer to clamp or keep
+#[inline(always)]
+pub fn oklab_to_rgb<S: Simd>(l: S::Vf32, a: S::Vf32, b: S::Vf32) -> (S::Vf32, S::Vf32, S::Vf32) {
+    unsafe {
+        let c = |v: f32| S::set1_ps(v);
+        let l_ = l + c(0.3963377774) * a + c(0.2158037573) * b;
+        let m_ = l - c(0.1055613458) * a - c(0.0638541728) * b;
+        let s_ = l - c(0.0894841775) * a - c(1.2914855480) * b;
+        let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+        let r = c(4.0767416621) * l - c(3.3077115913) * m + c(0.2309699292) * s;
+        let g = c(-1.2684380046) * l + c(2.6097574011) * m - c(0.3413193965) * s;
+        let b = c(-0.0041960863) * l - c(0.7034186147) * m + c(1.7076147010) * s;
+        (
+            linear_to_srgb_simd::<S>(r),
+            linear_to_srgb_simd::<S>(g),
+            linear_to_srgb_simd::<S>(b),
+        )
+    }
+}
+
+/// OkLch to sRGB from 0 to 1, h is in radians
+#[inline(always)]
+pub fn oklch_to_rgb<S: Simd>(l: S::Vf32, c: S::Vf32, h: S::Vf32) -> (S::Vf32, S::Vf32, S::Vf32) {
+    unsafe { oklab_to_rgb::<S>(l, c * S::fast_cos_ps(h), c * S::fast_sin_ps(h)) }
+}
+
+// linear_to_srgb for every lane, negative values are encoded as the negative
+// of their size so overshoots stay overshoots
+#[inline(always)]
+fn linear_to_srgb_simd<S: Simd>(v: S::Vf32) -> S::Vf32 {
+    unsafe {
+        let size = S::abs_ps(v);
+        let curve = S::set1_ps(1.055) * S::fast_exp_ps(S::fast_ln_ps(size) / S::set1_ps(2.4))
+            - S::set1_ps(0.055);
+        let straight = size * S::set1_ps(12.92);
+        let encoded = S::blendv_ps(straight, curve, S::cmpgt_ps(size, S::set1_ps(0.0031308)));
+        S::blendv_ps(
+            encoded,
+            S::setzero_ps() - encoded,
+            S::cmplt_ps(v, S::setzero_ps()),
+        )
+    }
+}
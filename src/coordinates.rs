@@ -0,0 +1,385 @@
+use crate::parser::*;
+use crate::pic::*;
+use rand::rngs::StdRng;
+use rand::*;
+use simdeez::*;
+use std::sync::mpsc::*;
+use CoordinateSystem::*;
+
+// Elliptic coordinates put their foci this far left and right of the middle
+const ELLIPTIC_FOCUS: f32 = 0.5;
+// keeps the mappings that divide by a distance finite at the middle
+const MIN_DISTANCE_SQUARED: f32 = 0.000001;
+// 1 in this many coordinate mutations of a Mobius pic nudges its parameters
+// instead of picking a new system
+const MOBIUS_NUDGE_CHANCE: usize = 2;
+const MOBIUS_NUDGE: f32 = 0.25;
+
+/// The complex map (a * z + b) / (c * z + d), where z is x + y i and each
+/// parameter is a (real, imaginary) pair
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MobiusParams {
+    pub a: (f32, f32),
+    pub b: (f32, f32),
+    pub c: (f32, f32),
+    pub d: (f32, f32),
+}
+
+impl MobiusParams {
+    /// Near the identity map, so the picture is bent rather than scrambled
+    pub fn random(rng: &mut StdRng) -> MobiusParams {
+        let mut near = |v: f32| (v + rng.gen_range(-0.5, 0.5), rng.gen_range(-0.5, 0.5));
+        MobiusParams {
+            a: near(1.0),
+            b: near(0.0),
+            c: near(0.0),
+            d: near(1.0),
+        }
+    }
+
+    fn parameters_mut(&mut self) -> [&mut f32; 8] {
+        [
+            &mut self.a.0,
+            &mut self.a.1,
+            &mut self.b.0,
+            &mut self.b.1,
+            &mut self.c.0,
+            &mut self.c.1,
+            &mut self.d.0,
+            &mut self.d.1,
+        ]
+    }
+
+    pub fn to_lisp(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {}",
+            self.a.0, self.a.1, self.b.0, self.b.1, self.c.0, self.c.1, self.d.0, self.d.1
+        )
+    }
+}
+
+/// How a pixel position turns into the X and Y its trees see. Each maps the
+/// picture's -1..1 square through the named projection
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CoordinateSystem {
+    Polar,
+    Cartesian,
+    /// Log of the radius and the angle, circles become evenly spaced lines
+    LogPolar,
+    /// Inside of the unit circle turned out and the outside turned in
+    Inversion,
+    /// Confocal ellipses and hyperbolas around two foci
+    Elliptic,
+    /// The middle bulged out like a lens
+    Fisheye,
+    Mobius(MobiusParams),
+}
+
+impl CoordinateSystem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Polar => "Polar",
+            Cartesian => "Cartesian",
+            LogPolar => "LogPolar",
+            Inversion => "Inversion",
+            Elliptic => "Elliptic",
+            Fisheye => "Fisheye",
+            Mobius(_) => "Mobius",
+        }
+    }
+
+    pub fn to_lisp(&self) -> String {
+        match self {
+            Mobius(mobius) => format!("Mobius {}", mobius.to_lisp()),
+            _ => self.name().to_string(),
+        }
+    }
+
+    pub fn random(rng: &mut StdRng) -> CoordinateSystem {
+        match rng.gen_range(0, 7) {
+            0 => Polar,
+            1 => Cartesian,
+            2 => LogPolar,
+            3 => Inversion,
+            4 => Elliptic,
+            5 => Fisheye,
+            _ => Mobius(MobiusParams::random(rng)),
+        }
+    }
+
+    /// Another system, or for Mobius sometimes the same one with a parameter nudged
+    pub fn mutate(&self, rng: &mut StdRng) -> CoordinateSystem {
+        match self {
+            Mobius(mobius) if rng.gen_range(0, MOBIUS_NUDGE_CHANCE) == 0 => {
+                let mut mobius = *mobius;
+                let mut parameters = mobius.parameters_mut();
+                let i = rng.gen_range(0, parameters.len());
+                *parameters[i] += rng.gen_range(-MOBIUS_NUDGE, MOBIUS_NUDGE);
+                Mobius(mobius)
+            }
+            _ => CoordinateSystem::random(rng),
+        }
+    }
+
+    /// Maps a row of pixel positions into this system
+    #[inline(always)]
+    pub fn map<S: Simd>(&self, x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32) {
+        unsafe {
+            match self {
+                Polar => cartesian_to_polar::<S>(x, y),
+                Cartesian => (x, y),
+                LogPolar => {
+                    let (r, theta) = cartesian_to_polar::<S>(x, y);
+                    let r = S::max_ps(r, S::set1_ps(MIN_DISTANCE_SQUARED));
+                    (S::fast_ln_ps(r), theta)
+                }
+                Inversion => {
+                    let r2 = S::max_ps(x * x + y * y, S::set1_ps(MIN_DISTANCE_SQUARED));
+                    (x / r2, y / r2)
+                }
+                Elliptic => {
+                    // mu from the sum of the distances to the foci, nu from
+                    // their difference, with nu's sign following y
+                    let focus = S::set1_ps(ELLIPTIC_FOCUS);
+                    let one = S::set1_ps(1.0);
+                    let y2 = y * y;
+                    let d1 = S::sqrt_ps((x + focus) * (x + focus) + y2);
+                    let d2 = S::sqrt_ps((x - focus) * (x - focus) + y2);
+                    let scale = S::set1_ps(0.5 / ELLIPTIC_FOCUS);
+                    let cosh_mu = S::max_ps((d1 + d2) * scale, one);
+                    let cos_nu = S::max_ps(S::min_ps((d1 - d2) * scale, one), S::set1_ps(-1.0));
+                    let mu = S::fast_ln_ps(cosh_mu + S::sqrt_ps(cosh_mu * cosh_mu - one));
+                    let sin_nu = S::sqrt_ps(one - cos_nu * cos_nu);
+                    let nu = S::fast_atan2_ps(sin_nu, cos_nu);
+                    let negative = S::cmplt_ps(y, S::setzero_ps());
+                    (mu, S::blendv_ps(nu, S::setzero_ps() - nu, negative))
+                }
+                Fisheye => {
+                    let r2 = x * x + y * y;
+                    let one = S::set1_ps(1.0);
+                    let depth = S::sqrt_ps(S::max_ps(one - r2, S::setzero_ps()));
+                    let factor = S::set1_ps(2.0) * (one - depth)
+                        / S::max_ps(r2, S::set1_ps(MIN_DISTANCE_SQUARED));
+                    (x * factor, y * factor)
+                }
+                Mobius(m) => {
+                    let complex = |(re, im): (f32, f32)| (S::set1_ps(re), S::set1_ps(im));
+                    let (ar, ai) = complex(m.a);
+                    let (br, bi) = complex(m.b);
+                    let (cr, ci) = complex(m.c);
+                    let (dr, di) = complex(m.d);
+                    let nr = ar * x - ai * y + br;
+                    let ni = ar * y + ai * x + bi;
+                    let dnr = cr * x - ci * y + dr;
+                    let dni = cr * y + ci * x + di;
+                    let d2 = S::max_ps(dnr * dnr + dni * dni, S::set1_ps(MIN_DISTANCE_SQUARED));
+                    ((nr * dnr + ni * dni) / d2, (ni * dnr - nr * dni) / d2)
+                }
+            }
+        }
+    }
+}
+
+/// Reads what follows Coord, up to but not including the close paren
+pub fn parse_coordinate_system(receiver: &Receiver<Token>) -> Result<CoordinateSystem, String> {
+    let name = expect_operations(
+        vec![
+            "polar",
+            "cartesian",
+            "logpolar",
+            "inversion",
+            "elliptic",
+            "fisheye",
+            "mobius",
+        ],
+        receiver,
+    )?;
+    Ok(match &name.to_lowercase()[..] {
+        "polar" => Polar,
+        "cartesian" => Cartesian,
+        "logpolar" => LogPolar,
+        "inversion" => Inversion,
+        "elliptic" => Elliptic,
+        "fisheye" => Fisheye,
+        _ => {
+            let mut mobius = MobiusParams {
+                a: (1.0, 0.0),
+                b: (0.0, 0.0),
+                c: (0.0, 0.0),
+                d: (1.0, 0.0),
+            };
+            for parameter in mobius.parameters_mut().iter_mut() {
+                **parameter = expect_constant(receiver)?;
+            }
+            Mobius(mobius)
+        }
+    })
+}
+
+#[inline(always)]
+pub fn cartesian_to_polar<S: Simd>(x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32) {
+    unsafe {
+        let zero = S::set1_ps(0.0);
+        let pi = S::set1_ps(3.14159);
+        let pix2 = S::set1_ps(3.14159 * 2.0);
+
+        let mask = S::cmpge_ps(x, zero);
+        let adjust = S::blendv_ps(pi, zero, mask);
+        let mask = S::cmplt_ps(y, zero) & mask;
+        let adjust = S::blendv_ps(adjust, pix2, mask);
+
+        let r = S::sqrt_ps(x * x + y * y);
+        let theta = S::fast_atan_ps(y / x) + adjust;
+        (r, theta)
+    }
+}
+
+/// Which part of the plane a render covers. Pixels span -1..1 across the
+/// image before the viewport is applied, so the default covers -1..1 on
+/// both axes however wide the image is
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    pub center: (f32, f32),
+    /// Half the height of the window on the plane
+    pub scale: f32,
+    /// Radians counterclockwise
+    pub rotation: f32,
+    /// Width over height of the window on the plane
+    pub aspect: f32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            center: (0.0, 0.0),
+            scale: 1.0,
+            rotation: 0.0,
+            aspect: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    /// The default window widened or narrowed so a w by h image isn't stretched
+    pub fn fit(w: usize, h: usize) -> Viewport {
+        Viewport {
+            aspect: w as f32 / h as f32,
+            ..Viewport::default()
+        }
+    }
+
+    /// Where the image position (u, v), each from -1 to 1, lands on the plane
+    pub fn to_plane(&self, u: f32, v: f32) -> (f32, f32) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let u = u * self.aspect;
+        (
+            self.center.0 + self.scale * (u * cos - v * sin),
+            self.center.1 + self.scale * (u * sin + v * cos),
+        )
+    }
+
+    /// Zooms by factor, under 1 zooms in, keeping the plane point under (u, v) still
+    pub fn zoom(&self, factor: f32, u: f32, v: f32) -> Viewport {
+        let (px, py) = self.to_plane(u, v);
+        Viewport {
+            center: (
+                px + (self.center.0 - px) * factor,
+                py + (self.center.1 - py) * factor,
+            ),
+            scale: self.scale * factor,
+            ..*self
+        }
+    }
+
+    /// The viewport that renders just the (x0, y0, tw, th) part of a w by h
+    /// image, with every sample moved (ox, oy) pixels
+    pub fn window(
+        &self,
+        w: usize,
+        h: usize,
+        (x0, y0, tw, th): (usize, usize, usize, usize),
+        ox: f32,
+        oy: f32,
+    ) -> Viewport {
+        // a single column sits where row puts it, at the left edge
+        let u_span = (w.max(2) - 1) as f32;
+        let u_scale = tw.saturating_sub(1) as f32 / u_span;
+        let u_offset = -1.0 + 2.0 * (x0 as f32 + ox) / u_span + u_scale;
+        let v_scale = th as f32 / h as f32;
+        let v_offset = -1.0 + 2.0 * (y0 as f32 + oy) / h as f32 + v_scale;
+        Viewport {
+            center: self.to_plane(u_offset, v_offset),
+            scale: self.scale * v_scale,
+            aspect: self.aspect * u_scale / v_scale,
+            ..*self
+        }
+    }
+
+    /// The first simd lanes of pixel row y_pixel on the plane, and how far
+    /// they move each step along the row
+    pub fn row<S: Simd>(
+        &self,
+        y_pixel: usize,
+        w: usize,
+        h: usize,
+    ) -> (S::Vf32, S::Vf32, S::Vf32, S::Vf32) {
+        unsafe {
+            let v = (y_pixel as f32 / h as f32) * 2.0 - 1.0;
+            // the first and last pixels sit on the edges, one pixel on the left one
+            let u_step = 2.0 / (w.max(2) - 1) as f32;
+            let mut x = S::setzero_ps();
+            let mut y = S::setzero_ps();
+            for i in (0..S::VF32_WIDTH).rev() {
+                let (px, py) = self.to_plane(-1.0 + (u_step * i as f32), v);
+                x[i] = px;
+                y[i] = py;
+            }
+            let (sin, cos) = self.rotation.sin_cos();
+            let step = u_step * S::VF32_WIDTH as f32;
+            let x_step = S::set1_ps(step * (self.scale * self.aspect * cos));
+            let y_step = S::set1_ps(step * (self.scale * self.aspect * sin));
+            (x, y, x_step, y_step)
+        }
+    }
+}
+
+/// Reads cx,cy,scale,rotation as given to --viewport
+pub fn parse_viewport(s: &str) -> Result<Viewport, String> {
+    let parts: Vec<f32> = s
+        .split(',')
+        .map(|part| part.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Unable to parse viewport {}", s))?;
+    if parts.len() != 4 {
+        return Err(format!(
+            "Expected a viewport like 0,0,1,0 for center x, center y, scale and rotation, found {}",
+            s
+        ));
+    }
+    Ok(Viewport {
+        center: (parts[0], parts[1]),
+        scale: parts[2],
+        rotation: parts[3],
+        aspect: 1.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simdeez::scalar::*;
+
+    #[test]
+    fn one_pixel_wide_images_stay_finite() {
+        let viewport = Viewport::default();
+        let (x, y, x_step, y_step) = viewport.row::<Scalar>(0, 1, 1);
+        for v in &[x[0], y[0], x_step[0], y_step[0]] {
+            assert!(v.is_finite());
+        }
+        for &(w, tw) in &[(1, 1), (8, 1), (1, 0)] {
+            let window = viewport.window(w, 4, (0, 0, tw, 2), 0.5, 0.5);
+            assert!(window.center.0.is_finite() && window.center.1.is_finite());
+            assert!(window.scale.is_finite() && window.aspect.is_finite());
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::parser::*;
+use crate::pic::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The genetic operation a pic came out of
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Origin {
+    Random,
+    Crossover,
+    Mutation,
+}
+
+impl Origin {
+    pub fn name(self) -> &'static str {
+        match self {
+            Origin::Random => "Random",
+            Origin::Crossover => "Crossover",
+            Origin::Mutation => "Mutation",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Origin> {
+        match &s.to_lowercase()[..] {
+            "random" => Some(Origin::Random),
+            "crossover" => Some(Origin::Crossover),
+            "mutation" => Some(Origin::Mutation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Record {
+    pub id: u64,
+    pub parents: Vec<u64>,
+    pub origin: Origin,
+    pub generation: usize,
+}
+
+/// Where every pic of a session came from. Ids are handed out in order and
+/// never reused, so they stay valid for as long as the session is kept. Each
+/// genealogy has an id of its own, since pic ids from different sessions
+/// start from 0 alike
+#[derive(Clone)]
+pub struct Genealogy {
+    pub id: u64,
+    records: HashMap<u64, Record>,
+    next_id: u64,
+}
+
+impl Genealogy {
+    pub fn new() -> Genealogy {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Genealogy::with_id(now.as_millis() as u64)
+    }
+
+    pub fn with_id(id: u64) -> Genealogy {
+        Genealogy {
+            id,
+            records: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Records a new pic and returns its id
+    pub fn add(&mut self, parents: Vec<u64>, origin: Origin, generation: usize) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert(Record {
+            id,
+            parents,
+            origin,
+            generation,
+        });
+        id
+    }
+
+    fn insert(&mut self, record: Record) {
+        self.next_id = self.next_id.max(record.id + 1);
+        self.records.insert(record.id, record);
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Record> {
+        self.records.get(&id)
+    }
+
+    /// Every record sorted by id, so parents come before their children
+    pub fn records(&self) -> Vec<&Record> {
+        let mut records: Vec<&Record> = self.records.values().collect();
+        records.sort_by_key(|record| record.id);
+        records
+    }
+
+    /// Ids of id and everything it descends from, sorted
+    pub fn lineage(&self, id: u64) -> Vec<u64> {
+        let mut seen = HashSet::new();
+        let mut todo = vec![id];
+        while let Some(id) = todo.pop() {
+            if seen.insert(id) {
+                if let Some(record) = self.get(id) {
+                    todo.extend(record.parents.iter());
+                }
+            }
+        }
+        let mut lineage: Vec<u64> = seen.into_iter().collect();
+        lineage.sort();
+        lineage
+    }
+
+    /// The whole session as a Graphviz digraph with edges from parents to children
+    pub fn to_dot(&self) -> String {
+        self.records_to_dot(&self.records())
+    }
+
+    /// Just the ancestors of id as a Graphviz digraph
+    pub fn lineage_to_dot(&self, id: u64) -> String {
+        let records: Vec<&Record> = self
+            .lineage(id)
+            .iter()
+            .filter_map(|id| self.get(*id))
+            .collect();
+        self.records_to_dot(&records)
+    }
+
+    fn records_to_dot(&self, records: &[&Record]) -> String {
+        let mut dot = "digraph lineage {\n".to_string();
+        for record in records {
+            dot += &format!(
+                "    n{} [label=\"{}\\ngeneration {}\\n{}\"];\n",
+                record.id,
+                record.id,
+                record.generation,
+                record.origin.name()
+            );
+        }
+        for record in records {
+            for parent in &record.parents {
+                dot += &format!("    n{} -> n{};\n", parent, record.id);
+            }
+        }
+        dot + "}\n"
+    }
+
+    pub fn to_lisp(&self) -> String {
+        let mut lisp = format!("( Genealogy ( Id {} )", self.id);
+        for record in self.records() {
+            let parents: Vec<String> = record.parents.iter().map(|p| p.to_string()).collect();
+            lisp += &format!(
+                "\n( Record {} {} {} ( Parents {} ) )",
+                record.id,
+                record.generation,
+                record.origin.name(),
+                parents.join(" ")
+            );
+        }
+        lisp + " )"
+    }
+}
+
+// ( Record id generation origin ( Parents ... ) ), after "( Record"
+fn parse_record(receiver: &Receiver<Token>) -> Result<Record, String> {
+    let id = expect_integer(receiver)?;
+    let generation = expect_integer(receiver)? as usize;
+    let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    let origin = match token {
+        Token::Operation(s, line_number) => Origin::from_name(s)
+            .ok_or_else(|| format!("Unknown origin {} on line {}", s, line_number))?,
+        _ => {
+            return Err(format!(
+                "Expected origin on line {}",
+                extract_line_number(&token)
+            ))
+        }
+    };
+    expect_open_paren(receiver)?;
+    expect_operation("parents", receiver)?;
+    let parents = expect_integers(receiver)?;
+    expect_close_paren(receiver)?;
+    Ok(Record {
+        id,
+        parents,
+        origin,
+        generation,
+    })
+}
+
+pub fn parse_genealogy(receiver: &Receiver<Token>) -> Result<Genealogy, String> {
+    expect_open_paren(receiver)?;
+    expect_operation("genealogy", receiver)?;
+    parse_records(receiver)
+}
+
+/// The rest of a genealogy once "( Genealogy" has been read. Genealogies
+/// saved before they had an id get 0
+pub fn parse_records(receiver: &Receiver<Token>) -> Result<Genealogy, String> {
+    let mut genealogy = Genealogy::with_id(0);
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => break,
+            Token::OpenParen(_) => {
+                match &expect_operations(vec!["id", "record"], receiver)?.to_lowercase()[..] {
+                    "id" => {
+                        genealogy.id = expect_integer(receiver)?;
+                        expect_close_paren(receiver)?;
+                    }
+                    _ => genealogy.insert(parse_record(receiver)?),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Expected '(' on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+    }
+    Ok(genealogy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0 and 1 are random, 2 is crossed from both, 3 is mutated from 2 and
+    // 4 is an unrelated mutation of 1
+    fn family() -> Genealogy {
+        let mut genealogy = Genealogy::with_id(77);
+        let a = genealogy.add(Vec::new(), Origin::Random, 0);
+        let b = genealogy.add(Vec::new(), Origin::Random, 0);
+        let c = genealogy.add(vec![a, b], Origin::Crossover, 1);
+        genealogy.add(vec![c], Origin::Mutation, 2);
+        genealogy.add(vec![b], Origin::Mutation, 1);
+        genealogy
+    }
+
+    #[test]
+    fn lisp_round_trip() {
+        let genealogy = family();
+        let lisp = genealogy.to_lisp();
+        let (sender, receiver) = channel();
+        Lexer::begin_lexing(&lisp, sender);
+        let loaded = parse_genealogy(&receiver).unwrap();
+        assert_eq!(loaded.id, 77);
+        assert_eq!(loaded.records().len(), 5);
+        assert_eq!(loaded.get(2).unwrap().parents, vec![0, 1]);
+        assert_eq!(loaded.get(3).unwrap().origin, Origin::Mutation);
+        assert_eq!(loaded.get(3).unwrap().generation, 2);
+        assert_eq!(loaded.to_lisp(), lisp);
+        // ids carry on after the loaded ones
+        assert_eq!(loaded.clone().add(Vec::new(), Origin::Random, 3), 5);
+    }
+
+    #[test]
+    fn lineage_follows_parents() {
+        let genealogy = family();
+        assert_eq!(genealogy.lineage(3), vec![0, 1, 2, 3]);
+        assert_eq!(genealogy.lineage(4), vec![1, 4]);
+        assert_eq!(genealogy.lineage(0), vec![0]);
+    }
+
+    #[test]
+    fn dot_has_every_record_and_edge() {
+        let genealogy = family();
+        let dot = genealogy.to_dot();
+        assert!(dot.starts_with("digraph lineage {\n") && dot.ends_with("}\n"));
+        assert!(dot.contains("    n2 [label=\"2\\ngeneration 1\\nCrossover\"];\n"));
+        for edge in &["n0 -> n2", "n1 -> n2", "n2 -> n3", "n1 -> n4"] {
+            assert!(dot.contains(edge), "missing {}", edge);
+        }
+        let lineage = genealogy.lineage_to_dot(3);
+        assert!(lineage.contains("n0 -> n2") && lineage.contains("n2 -> n3"));
+        assert!(!lineage.contains("n4"));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::operator::*;
+use crate::parser::*;
+use crate::pic::*;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::mpsc::*;
+
+#[derive(Clone, Copy)]
+pub struct OperatorSettings {
+    pub weight: usize,
+    pub enabled: bool,
+}
+
+impl OperatorSettings {
+    pub fn effective_weight(&self) -> usize {
+        if self.enabled {
+            self.weight
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LeafWeights {
+    pub x: usize,
+    pub y: usize,
+    pub t: usize,  // only used when generating video
+    pub t2: usize, // likewise, only moves when the video loops
+    pub constant: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GenerationMethod {
+    /// Adds a random number of operators at random spots, then fills in leaves
+    Random,
+    Grow,
+    Full,
+    RampedHalfAndHalf,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LimitPolicy {
+    /// Keep the parent when the child is too big
+    Reject,
+    /// Redo the genetic operation a few times before keeping the parent
+    Retry(usize),
+    /// Cut the child down to size
+    Prune,
+}
+
+/// Keeps trees from growing without bound when they are bred
+#[derive(Clone, Copy)]
+pub struct BloatLimits {
+    pub max_depth: usize,
+    pub max_size: usize,
+    pub policy: LimitPolicy,
+    /// How much rating a node is worth when picking parents, 0 turns it off
+    pub parsimony: f32,
+}
+
+/// Controls what random trees look like. Load one with `load_generation_config`
+/// to keep a personal style, a file looks like:
+///
+/// ( Generation
+///     ( Weight FBM 4 )
+///     ( Disable Mod )
+///     ( Leaf Constant 2 )
+///     ( ConstantRange -0.5 0.5 )
+///     ( Method RampedHalfAndHalf )
+///     ( MaxDepth 12 )
+///     ( MaxSize 200 )
+///     ( Limit Retry 5 )
+///     ( Parsimony 0.005 ) )
+#[derive(Clone)]
+pub struct GenerationConfig {
+    pub method: GenerationMethod,
+    pub limits: BloatLimits,
+    pub operators: HashMap<Operator, OperatorSettings>,
+    pub picture: OperatorSettings,
+    pub leaves: LeafWeights,
+    pub constant_min: f32,
+    pub constant_max: f32,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            method: GenerationMethod::Random,
+            limits: BloatLimits {
+                max_depth: 12,
+                max_size: 200,
+                policy: LimitPolicy::Retry(5),
+                parsimony: 0.0,
+            },
+            operators: OPERATORS
+                .iter()
+                .map(|op| {
+                    let settings = OperatorSettings {
+                        weight: op.weight(),
+                        enabled: true,
+                    };
+                    (*op, settings)
+                })
+                .collect(),
+            picture: OperatorSettings {
+                weight: 1,
+                enabled: true,
+            },
+            leaves: LeafWeights {
+                x: 1,
+                y: 1,
+                t: 1,
+                t2: 1,
+                constant: 1,
+            },
+            constant_min: -1.0,
+            constant_max: 1.0,
+        }
+    }
+}
+
+impl GenerationConfig {
+    pub fn operator_weight(&self, op: Operator) -> usize {
+        self.operators[&op].effective_weight()
+    }
+
+    /// Total weight of everything get_random_node can pick from
+    pub fn node_weight(&self, has_pictures: bool, video: bool) -> usize {
+        // Picture nodes aren't in the operator registry since they carry a name
+        let picture_weight = if has_pictures {
+            self.picture.effective_weight()
+        } else {
+            0
+        };
+        OPERATORS
+            .iter()
+            .filter(|op| video || !op.uses_time())
+            .map(|op| self.operator_weight(*op))
+            .sum::<usize>()
+            + picture_weight
+    }
+
+    pub fn leaf_weight(&self, video: bool) -> usize {
+        let t = if video {
+            self.leaves.t + self.leaves.t2
+        } else {
+            0
+        };
+        self.leaves.x + self.leaves.y + t + self.leaves.constant
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.node_weight(false, false) == 0 {
+            return Err("Generation config leaves no operators to pick from".to_string());
+        }
+        if self.leaf_weight(false) == 0 {
+            return Err("Generation config leaves no leaves to pick from".to_string());
+        }
+        if self.constant_min >= self.constant_max {
+            return Err(format!(
+                "Constant range {} to {} is empty",
+                self.constant_min, self.constant_max
+            ));
+        }
+        Ok(())
+    }
+
+    fn settings_mut(
+        &mut self,
+        name: &str,
+        line_number: usize,
+    ) -> Result<&mut OperatorSettings, String> {
+        if name.to_lowercase() == "picture" {
+            return Ok(&mut self.picture);
+        }
+        match Operator::from_name(name) {
+            Some(op) => Ok(self.operators.get_mut(&op).unwrap()),
+            None => Err(format!("Unknown operator {} on line {}", name, line_number)),
+        }
+    }
+}
+
+/// Seed for the individual at index in a population made from seed, so any one
+/// of them can be made again without the rest
+pub fn individual_seed(seed: u64, index: usize) -> u64 {
+    // splitmix64, so neighbouring indexes get unrelated seeds
+    let mut z = seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+pub fn load_generation_config(path: &str) -> Result<GenerationConfig, String> {
+    let code = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read generation config {}: {}", path, e))?;
+    lisp_to_generation_config(&code)
+}
+
+pub fn lisp_to_generation_config(code: &str) -> Result<GenerationConfig, String> {
+    let (sender, receiver) = channel();
+    Lexer::begin_lexing(code, sender);
+    parse_generation_config(&receiver)
+}
+
+fn expect_name(receiver: &Receiver<Token>) -> Result<(String, usize), String> {
+    let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    match token {
+        Token::Operation(s, line_number) => Ok((s.to_string(), line_number)),
+        _ => Err(format!(
+            "Expected a name on line {}, found {:?}",
+            extract_line_number(&token),
+            token
+        )),
+    }
+}
+
+pub fn parse_generation_config(receiver: &Receiver<Token>) -> Result<GenerationConfig, String> {
+    let mut config = GenerationConfig::default();
+    expect_open_paren(receiver)?;
+    expect_operation("generation", receiver)?;
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => break,
+            Token::OpenParen(_) => (),
+            _ => {
+                return Err(format!(
+                    "Expected '(' on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+        let setting = expect_operations(
+            vec![
+                "weight",
+                "enable",
+                "disable",
+                "leaf",
+                "constantrange",
+                "method",
+                "maxdepth",
+                "maxsize",
+                "limit",
+                "parsimony",
+            ],
+            receiver,
+        )?;
+        match &setting.to_lowercase()[..] {
+            "weight" => {
+                let (name, line_number) = expect_name(receiver)?;
+                let weight = expect_constant(receiver)?;
+                config.settings_mut(&name, line_number)?.weight = weight.max(0.0) as usize;
+            }
+            "enable" => {
+                let (name, line_number) = expect_name(receiver)?;
+                config.settings_mut(&name, line_number)?.enabled = true;
+            }
+            "disable" => {
+                let (name, line_number) = expect_name(receiver)?;
+                config.settings_mut(&name, line_number)?.enabled = false;
+            }
+            "leaf" => {
+                let (name, line_number) = expect_name(receiver)?;
+                let weight = expect_constant(receiver)?.max(0.0) as usize;
+                match &name.to_lowercase()[..] {
+                    "x" => config.leaves.x = weight,
+                    "y" => config.leaves.y = weight,
+                    "t" => config.leaves.t = weight,
+                    "t2" => config.leaves.t2 = weight,
+                    "constant" => config.leaves.constant = weight,
+                    _ => return Err(format!("Unknown leaf {} on line {}", name, line_number)),
+                }
+            }
+            "method" => {
+                let (name, line_number) = expect_name(receiver)?;
+                config.method = match &name.to_lowercase()[..] {
+                    "random" => GenerationMethod::Random,
+                    "grow" => GenerationMethod::Grow,
+                    "full" => GenerationMethod::Full,
+                    "rampedhalfandhalf" => GenerationMethod::RampedHalfAndHalf,
+                    _ => return Err(format!("Unknown method {} on line {}", name, line_number)),
+                };
+            }
+            "maxdepth" => config.limits.max_depth = expect_constant(receiver)?.max(1.0) as usize,
+            "maxsize" => config.limits.max_size = expect_constant(receiver)?.max(1.0) as usize,
+            "limit" => {
+                let (name, line_number) = expect_name(receiver)?;
+                config.limits.policy = match &name.to_lowercase()[..] {
+                    "reject" => LimitPolicy::Reject,
+                    "retry" => LimitPolicy::Retry(expect_constant(receiver)?.max(0.0) as usize),
+                    "prune" => LimitPolicy::Prune,
+                    _ => return Err(format!("Unknown limit {} on line {}", name, line_number)),
+                };
+            }
+            "parsimony" => config.limits.parsimony = expect_constant(receiver)?,
+            _ => {
+                config.constant_min = expect_constant(receiver)?;
+                config.constant_max = expect_constant(receiver)?;
+            }
+        }
+        expect_close_paren(receiver)?;
+    }
+    config.validate()?;
+    Ok(config)
+}
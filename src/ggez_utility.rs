@@ -16,3 +16,21 @@ pub fn get_random_color(rng: &mut StdRng) -> Color {
     let b = rng.gen_range(0.0, 1.0);
     Color::new(r, g, b, 1.0)
 }
+
+/// Turns an sRGB channel from 0 to 1 into linear light
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Turns a linear light channel from 0 to 1 back into sRGB
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
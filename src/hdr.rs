@@ -0,0 +1,163 @@
+use crate::ggez_utility::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// tone mapping leaves values between 1 - KNEE and KNEE alone and bends the
+// rest smoothly into 0 to 1
+const TONE_MAP_KNEE: f32 = 0.8;
+
+/// What a renderer can write a channel into. Renderers hand over values where
+/// 0 is black and 1 is full brightness, which trees can overshoot either way
+pub trait Channel: Copy + Default + Send + Sync {
+    fn from_unit(v: f32) -> Self;
+    fn to_unit(self) -> f32;
+    /// Amount of light, for averaging samples
+    fn to_linear(self) -> f32;
+    fn from_linear(v: f32) -> Self;
+}
+
+impl Channel for u8 {
+    #[inline(always)]
+    fn from_unit(v: f32) -> u8 {
+        (v.max(0.0).min(1.0) * 255.0) as u8
+    }
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb_to_linear(self.to_unit())
+    }
+
+    // rounded, since averages fall between levels
+    fn from_linear(v: f32) -> u8 {
+        (linear_to_srgb(v.max(0.0).min(1.0)) * 255.0 + 0.5) as u8
+    }
+}
+
+// overshoots below 0 are treated as negative light of the same size
+impl Channel for f32 {
+    #[inline(always)]
+    fn from_unit(v: f32) -> f32 {
+        v
+    }
+
+    fn to_unit(self) -> f32 {
+        self
+    }
+
+    fn to_linear(self) -> f32 {
+        self.signum() * srgb_to_linear(self.abs())
+    }
+
+    fn from_linear(v: f32) -> f32 {
+        v.signum() * linear_to_srgb(v.abs())
+    }
+}
+
+/// How values outside 0 to 1 fit into an integer format
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutOfRange {
+    Clamp,
+    /// Brightness goes round, so 1.25 is 0.25
+    Wrap,
+    /// Values near the ends are squashed so overshoots keep some detail
+    ToneMap,
+}
+
+impl OutOfRange {
+    pub fn from_name(s: &str) -> Option<OutOfRange> {
+        match &s.to_lowercase()[..] {
+            "clamp" => Some(OutOfRange::Clamp),
+            "wrap" => Some(OutOfRange::Wrap),
+            "tonemap" => Some(OutOfRange::ToneMap),
+            _ => None,
+        }
+    }
+
+    /// Brings v into 0 to 1
+    pub fn apply(self, v: f32) -> f32 {
+        if v.is_nan() {
+            return 0.0;
+        }
+        match self {
+            OutOfRange::Clamp => v.max(0.0).min(1.0),
+            OutOfRange::Wrap => {
+                if v >= 0.0 && v <= 1.0 {
+                    v
+                } else {
+                    v - v.floor()
+                }
+            }
+            OutOfRange::ToneMap => {
+                let toe = 1.0 - TONE_MAP_KNEE;
+                let span = 1.0 - TONE_MAP_KNEE;
+                if v > TONE_MAP_KNEE {
+                    let t = (v - TONE_MAP_KNEE) / span;
+                    TONE_MAP_KNEE + span * t / (1.0 + t)
+                } else if v < toe {
+                    let t = (toe - v) / span;
+                    toe - span * t / (1.0 + t)
+                } else {
+                    v
+                }
+            }
+        }
+    }
+}
+
+/// Quantizes rgba floats from Pic::get_rgbaf32 to 16 bits a channel, alpha is
+/// only ever clamped
+pub fn to_rgba16(rgba: &[f32], policy: OutOfRange) -> Vec<u16> {
+    rgba.iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let v = if i % 4 == 3 {
+                OutOfRange::Clamp.apply(*v)
+            } else {
+                policy.apply(*v)
+            };
+            (v * 65535.0 + 0.5) as u16
+        })
+        .collect()
+}
+
+/// Writes rgba floats from Pic::get_rgbaf32 as a 16 bit png
+pub fn write_png16(
+    rgba: &[f32],
+    w: usize,
+    h: usize,
+    policy: OutOfRange,
+    path: &str,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    // png keeps 16 bit samples big endian
+    let bytes: Vec<u8> = to_rgba16(rgba, policy)
+        .iter()
+        .flat_map(|v| v.to_be_bytes().to_vec())
+        .collect();
+    writer.write_image_data(&bytes).map_err(|e| e.to_string())
+}
+
+/// Writes rgba floats from Pic::get_rgbaf32 as a pfm in linear light. Nothing
+/// is clamped, values past 1 stay bright and ones below 0 stay negative
+pub fn write_pfm(rgba: &[f32], w: usize, h: usize, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", w, h).into_bytes();
+    // pfm rows go from the bottom up, a negative scale means little endian
+    for row in rgba.chunks_exact(w * 4).rev() {
+        for pixel in row.chunks_exact(4) {
+            for c in &pixel[0..3] {
+                bytes.extend_from_slice(&c.to_linear().to_le_bytes());
+            }
+        }
+    }
+    out.write_all(&bytes)
+        .map_err(|e| format!("Unable to write {}: {}", path, e))
+}
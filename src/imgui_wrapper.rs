@@ -22,6 +22,7 @@ pub struct ImGuiWrapper {
     last_frame: Instant,
     mouse_state: MouseState,
     text_multiline: ImString,
+    seed_text: ImString,
 }
 
 impl ImGuiWrapper {
@@ -58,10 +59,12 @@ impl ImGuiWrapper {
             last_frame: Instant::now(),
             mouse_state: MouseState::default(),
             text_multiline: ImString::with_capacity(1024),
+            seed_text: ImString::with_capacity(32),
         }
     }
 
-    pub fn render(&mut self, ctx: &mut Context, hidpi_factor: f32) {
+    /// Returns a seed when a new population was asked for
+    pub fn render(&mut self, ctx: &mut Context, hidpi_factor: f32, seed: u64) -> Option<u64> {
         // Update mouse
         self.update_mouse();
 
@@ -93,6 +96,20 @@ impl ImGuiWrapper {
                 ui.input_text_multiline(im_str!("multiline"), t, [300., 100.])
                     .build();
             });
+        let seed_text = &mut self.seed_text;
+        let mut new_seed = None;
+        Window::new(im_str!("Seed"))
+            .size([300.0, 100.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text(format!("Seed: {}", seed));
+                ui.input_text(im_str!("seed"), seed_text).build();
+                if ui.button(im_str!("New population"), [120.0, 20.0]) {
+                    new_seed = match seed_text.to_str().trim() {
+                        "" => Some(rand::thread_rng().gen()),
+                        text => text.parse::<u64>().ok(),
+                    };
+                }
+            });
 
         // Render
         let (factory, _, encoder, _, render_target) = graphics::gfx_objects(ctx);
@@ -105,6 +122,7 @@ impl ImGuiWrapper {
                 draw_data,
             )
             .unwrap();
+        new_seed
     }
 
     pub fn update_keyboard(&mut self, ch: char) {
@@ -134,4 +152,8 @@ impl ImGuiWrapper {
     pub fn update_mouse_down(&mut self, pressed: (bool, bool, bool)) {
         self.mouse_state.pressed = pressed;
     }
+
+    pub fn update_mouse_wheel(&mut self, wheel: f32) {
+        self.mouse_state.wheel += wheel;
+    }
 }
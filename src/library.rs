@@ -0,0 +1,267 @@
+use crate::parser::*;
+use crate::pic::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LIBRARY_DIR: &str = "library";
+
+/// A picture kept in the library. Each one is stored as <id>.lisp next to a
+/// <id>.png thumbnail, the lisp looks like:
+///
+/// ( Favorite
+///     ( Id 1571425012345 )
+///     ( Name "blue swirl" )
+///     ( Tags "hsv" "noise" )
+///     ( Created 1571425012 )
+///     ( Genealogy 1571424000000 )
+///     ( Parents 4 17 )
+///     ( Seed 8815347390466042093 )
+///     ( HSV ... ) )
+///
+/// Bred pics are written with an empty ( Seed ) since only their parents can
+/// make them again
+#[derive(Clone)]
+pub struct Favorite {
+    pub id: u64,
+    pub name: String,
+    pub tags: Vec<String>,
+    /// Seconds since the unix epoch
+    pub created: u64,
+    /// Id of the session genealogy parents come from, pic ids are only
+    /// unique within one
+    pub genealogy: u64,
+    /// Genealogy ids of the pics this one was bred from
+    pub parents: Vec<u64>,
+    /// The sub-seed a pic of a random population was made from
+    pub seed: Option<u64>,
+    pub pic: Pic,
+}
+
+// the lexer only knows ascii and has no escapes, so keep names and tags to that
+fn clean_text(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii() && c != '"' { c } else { '?' })
+        .collect()
+}
+
+fn quote_all(strings: &[String]) -> String {
+    strings
+        .iter()
+        .map(|s| format!("\"{}\"", clean_text(s)))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Favorite {
+    pub fn new(name: &str, pic: Pic, seed: Option<u64>) -> Favorite {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Favorite {
+            id: now.as_millis() as u64,
+            name: name.to_string(),
+            tags: Vec::new(),
+            created: now.as_secs(),
+            genealogy: 0,
+            parents: Vec::new(),
+            seed,
+            pic,
+        }
+    }
+
+    pub fn to_lisp(&self) -> String {
+        format!(
+            "( Favorite\n( Id {} )\n( Name \"{}\" )\n( Tags {} )\n( Created {} )\n( Genealogy {} )\n( Parents {} )\n( Seed {} )\n{} )\n",
+            self.id,
+            clean_text(&self.name),
+            quote_all(&self.tags),
+            self.created,
+            self.genealogy,
+            self.parents
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+            self.seed.map_or(String::new(), |seed| seed.to_string()),
+            self.pic.to_lisp()
+        )
+    }
+
+    /// True if the name or any tag contains query, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.name.to_lowercase().contains(&query)
+            || self
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(&query))
+    }
+}
+
+pub struct Library {
+    dir: PathBuf,
+}
+
+impl Library {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Library, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Unable to create library {}: {}", dir.display(), e))?;
+        Ok(Library { dir })
+    }
+
+    fn lisp_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.lisp", id))
+    }
+
+    pub fn thumbnail_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.png", id))
+    }
+
+    /// Stores the favorite along with its rgba8 thumbnail. If the id is
+    /// already taken it is bumped until it isn't, the stored favorite is returned
+    pub fn add(
+        &self,
+        favorite: &Favorite,
+        thumbnail: &[u8],
+        w: usize,
+        h: usize,
+    ) -> Result<Favorite, String> {
+        let mut favorite = favorite.clone();
+        while self.lisp_path(favorite.id).exists() {
+            favorite.id += 1;
+        }
+        image::save_buffer(
+            self.thumbnail_path(favorite.id),
+            thumbnail,
+            w as u32,
+            h as u32,
+            image::ColorType::RGBA(8),
+        )
+        .map_err(|e| format!("Unable to write thumbnail for {}: {}", favorite.id, e))?;
+        fs::write(self.lisp_path(favorite.id), favorite.to_lisp())
+            .map_err(|e| format!("Unable to write favorite {}: {}", favorite.id, e))?;
+        Ok(favorite)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Favorite, String> {
+        let path = self.lisp_path(id);
+        let code = fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read favorite {}: {}", path.display(), e))?;
+        lisp_to_favorite(&code)
+    }
+
+    /// Every favorite in the library, oldest first. Files that can't be read
+    /// or parsed are skipped with a warning
+    pub fn list(&self) -> Result<Vec<Favorite>, String> {
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|e| format!("Unable to read library {}: {}", self.dir.display(), e))?;
+        let mut favorites = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().map_or(false, |ext| ext == "lisp") {
+                // one damaged file shouldn't hide the rest of the library
+                let favorite = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|code| lisp_to_favorite(&code));
+                match favorite {
+                    Ok(favorite) => favorites.push(favorite),
+                    Err(e) => println!("Skipping favorite {}: {}", path.display(), e),
+                }
+            }
+        }
+        favorites.sort_by_key(|favorite| (favorite.created, favorite.id));
+        Ok(favorites)
+    }
+
+    pub fn search(&self, query: &str) -> Result<Vec<Favorite>, String> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|favorite| favorite.matches(query))
+            .collect())
+    }
+
+    pub fn delete(&self, id: u64) -> Result<(), String> {
+        fs::remove_file(self.lisp_path(id))
+            .map_err(|e| format!("Unable to delete favorite {}: {}", id, e))?;
+        // a missing thumbnail shouldn't stop the favorite going away
+        let _ = fs::remove_file(self.thumbnail_path(id));
+        Ok(())
+    }
+}
+
+pub fn lisp_to_favorite(code: &str) -> Result<Favorite, String> {
+    let (sender, receiver) = channel();
+    Lexer::begin_lexing(code, sender);
+    parse_favorite(&receiver)
+}
+
+// reads quoted strings up to and including the closing paren
+fn expect_texts(receiver: &Receiver<Token>) -> Result<Vec<String>, String> {
+    let mut texts = Vec::new();
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => return Ok(texts),
+            Token::Text(text, _) => texts.push(text.to_string()),
+            _ => {
+                return Err(format!(
+                    "Expected quoted text on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+    }
+}
+
+pub fn parse_favorite(receiver: &Receiver<Token>) -> Result<Favorite, String> {
+    expect_open_paren(receiver)?;
+    expect_operation("favorite", receiver)?;
+
+    expect_open_paren(receiver)?;
+    expect_operation("id", receiver)?;
+    let id = expect_integer(receiver)?;
+    expect_close_paren(receiver)?;
+
+    expect_open_paren(receiver)?;
+    expect_operation("name", receiver)?;
+    let name = expect_texts(receiver)?.join(" ");
+
+    expect_open_paren(receiver)?;
+    expect_operation("tags", receiver)?;
+    let tags = expect_texts(receiver)?;
+
+    expect_open_paren(receiver)?;
+    expect_operation("created", receiver)?;
+    let created = expect_integer(receiver)?;
+    expect_close_paren(receiver)?;
+
+    // favorites from before genealogies had ids go straight to the parents
+    expect_open_paren(receiver)?;
+    let mut genealogy = 0;
+    if expect_operations(vec!["genealogy", "parents"], receiver)?.to_lowercase() == "genealogy" {
+        genealogy = expect_integer(receiver)?;
+        expect_close_paren(receiver)?;
+        expect_open_paren(receiver)?;
+        expect_operation("parents", receiver)?;
+    }
+    let parents = expect_integers(receiver)?;
+
+    expect_open_paren(receiver)?;
+    expect_operation("seed", receiver)?;
+    let seed = expect_integers(receiver)?.first().cloned();
+
+    let pic = parse_pic(receiver)?;
+    expect_close_paren(receiver)?;
+    Ok(Favorite {
+        id,
+        name,
+        tags,
+        created,
+        genealogy,
+        parents,
+        seed,
+        pic,
+    })
+}
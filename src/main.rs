@@ -1,29 +1,53 @@
 // todo
-// - fix up gradient to work properly when parsing
-// - cross breeding of picture expressions
 // - load up thumbnails in a background thread so ui isn't blocked
 
 extern crate ggez;
 
 mod actual_picture;
+mod analysis;
+mod antialias;
 mod apt;
+mod breed;
+mod color;
+mod coordinates;
+mod genealogy;
+mod generation;
+mod hdr;
 mod ggez_utility;
 mod imgui_wrapper;
+mod library;
+mod noise;
+mod operator;
 mod parser;
 mod pic;
+mod session;
 mod stack_machine;
+mod transform;
 mod ui;
+mod video;
 
 use crate::actual_picture::*;
+use crate::analysis::*;
+use crate::antialias::*;
+use crate::breed::*;
+use crate::coordinates::*;
+use crate::genealogy::*;
+use crate::generation::*;
+use crate::hdr::*;
 use crate::imgui_wrapper::ImGuiWrapper;
+use crate::library::*;
+use crate::noise::*;
 use crate::parser::*;
 use crate::pic::*;
+use crate::session::*;
+use crate::stack_machine::*;
 use crate::ui::*;
+use crate::video::*;
 use ggez::conf;
 use ggez::event::{self, EventHandler, KeyCode, KeyMods, MouseButton};
 use ggez::graphics::{self, Image};
 use ggez::timer;
-use ggez::{Context, GameResult};
+use ggez::{Context, GameError, GameResult};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use rand::*;
@@ -46,12 +70,31 @@ use std::time::Instant;
 const WIDTH: usize = 1920;
 const HEIGHT: usize = 1080;
 const VIDEO_DURATION: f32 = 5000.0; //milliseconds
+// the loops played in video mode are kept small so they render quickly
+const VIDEO_FPS: u16 = 15;
+const VIDEO_THUMB_SIZE: usize = 128;
+const VIDEO_ZOOM_SCALE: usize = 2;
+const CALIBRATION_SAMPLES: usize = 2000000;
+// each notch of the mouse wheel zooms the zoom view by this much
+const WHEEL_ZOOM: f32 = 0.8;
+// --adaptive without --antialias takes this many samples each way
+const ADAPTIVE_SAMPLES: usize = 3;
 
 const THUMB_ROWS: u16 = 6;
 const THUMB_COLS: u16 = 7;
+const THUMB_SIZE: usize = 256;
+// how many times to regenerate a degenerate thumbnail before keeping it anyway
+const DEGENERATE_RETRIES: usize = 20;
 
+// node counts for the Random generation method
 const TREE_MIN: usize = 1;
 const TREE_MAX: usize = 40;
+// depths for the Grow, Full and RampedHalfAndHalf generation methods
+const TREE_MIN_DEPTH: usize = 2;
+const TREE_MAX_DEPTH: usize = 7;
+
+const SESSION_FILE: &str = "session.lisp";
+const LINEAGE_FILE: &str = "lineage.dot";
 
 struct RwArc<T>(Arc<RwLock<T>>);
 impl<T> RwArc<T> {
@@ -70,6 +113,11 @@ impl<T> RwArc<T> {
     pub fn clone(&self) -> RwArc<T> {
         RwArc(self.0.clone())
     }
+
+    // true once nothing but a background thread still holds it
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.0) == 1
+    }
 }
 
 enum GameState {
@@ -83,51 +131,196 @@ enum BackgroundImage {
     Complete(graphics::Image),
 }
 
+enum BackgroundVideo {
+    NotYet,
+    Almost(Vec<Vec<u8>>, usize, usize),
+    Complete(Vec<graphics::Image>),
+}
+
+impl BackgroundVideo {
+    // turns frames rendered in the background into images, which needs the context
+    fn complete(video: &RwArc<BackgroundVideo>, ctx: &mut Context) {
+        let maybe_frames = match &*video.read() {
+            BackgroundVideo::Almost(frames, w, h) => Some(
+                frames
+                    .iter()
+                    .map(|frame| {
+                        graphics::Image::from_rgba8(ctx, *w as u16, *h as u16, &frame[0..])
+                            .unwrap()
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        if let Some(frames) = maybe_frames {
+            video.write(BackgroundVideo::Complete(frames));
+        }
+    }
+}
+
 struct MainState {
     state: GameState,
     mouse_state: MouseState,
     imgui_wrapper: ImGuiWrapper,    
     img_buttons: Vec<Button>,
     pics: Vec<Pic>,
+    ratings: Vec<f32>,
+    // genealogy id of each pic
+    ids: Vec<u64>,
+    genealogy: Genealogy,
+    generation: usize,
+    // individual_seed(seed, i) for each pic of a random population, bred
+    // pics have none since they come from their parents
+    seeds: Vec<Option<u64>>,
     dt: std::time::Duration,
     frame_elapsed: f32,
+    // only ever drawn from for breed seeds, so the seed it was made from and
+    // the number of draws since pin down where it is for a saved session
     rng: StdRng,
+    rng_seed: u64,
+    rng_draws: u64,
+    // the seed the current population was made or bred from
+    seed: u64,
+    library: Library,
+    generation_config: GenerationConfig,
+    degenerate_thresholds: DegenerateThresholds,
     zoom_image: RwArc<BackgroundImage>,
+    // video mode generates pics with T and plays them as loops
+    video: bool,
+    thumb_videos: Vec<RwArc<BackgroundVideo>>,
+    zoom_video: RwArc<BackgroundVideo>,
+    // which pic is showing in the zoom view
+    zoom_index: usize,
+    // the part of the plane the zoom view shows, the mouse wheel zooms it
+    zoom_viewport: Viewport,
+    video_settings: VideoSettings,
     pictures: Arc<HashMap<String, ActualPicture>>,
 }
 
 impl MainState {
-    fn gen_population(&mut self, ctx: &mut Context) {
-        // todo make this layout code less dumb
+    // sorted so the same seed picks the same pictures every run
+    fn pic_names(&self) -> Vec<&String> {
+        let mut pic_names: Vec<&String> = self.pictures.keys().collect();
+        pic_names.sort();
+        pic_names
+    }
+
+    /// Makes the pic at index in the population made from seed, without
+    /// needing any of the others
+    fn generate_individual(&self, seed: u64, index: usize) -> (Pic, Vec<u8>) {
+        let pic_names = &self.pic_names();
+        let (min, max) = if self.generation_config.method == GenerationMethod::Random {
+            (TREE_MIN, TREE_MAX)
+        } else {
+            (TREE_MIN_DEPTH, TREE_MAX_DEPTH)
+        };
+        let rng = &mut StdRng::seed_from_u64(individual_seed(seed, index));
+        let mut attempt = 0;
+        loop {
+            let config = &self.generation_config;
+            let pic = Pic::new_random(min, max, self.video, rng, pic_names, config);
+            let thumbnail = self.render_thumbnail(&pic);
+            let stats = analyze(&thumbnail, THUMB_SIZE, THUMB_SIZE);
+            match stats.degenerate_reason(&self.degenerate_thresholds) {
+                Some(_) if attempt < DEGENERATE_RETRIES => attempt += 1,
+                _ => return (pic, thumbnail),
+            }
+        }
+    }
+
+    fn gen_population(&mut self, ctx: &mut Context, seed: u64) {
+        let now = Instant::now();
+        let size = (THUMB_ROWS * THUMB_COLS) as usize;
+        let pics = (0..size)
+            .map(|i| self.generate_individual(seed, i))
+            .collect();
+        self.generation = 0;
+        let ids = (0..size)
+            .map(|_| self.genealogy.add(Vec::new(), Origin::Random, 0))
+            .collect();
+        let seeds = (0..size).map(|i| Some(individual_seed(seed, i))).collect();
+        self.set_population(ctx, pics, ids, seed, seeds);
+        println!("genpop seed:{} elapsed:{}", seed, now.elapsed().as_millis());
+    }
+
+    fn render_thumbnail(&self, pic: &Pic) -> Vec<u8> {
+        let (pictures, size) = (self.pictures.clone(), THUMB_SIZE);
+        let (viewport, antialias) = (&Viewport::default(), self.video_settings.antialias);
+        pic.get_rgba8::<Avx2>(false, pictures, size, size, viewport, antialias, Time::linear(0.0))
+    }
+
+    // breeds the next population from the pics rated so far
+    fn breed(&mut self, ctx: &mut Context) {
+        let mut parents = Vec::new();
+        let mut ratings = Vec::new();
+        let mut indexes = Vec::new();
+        for (i, (pic, rating)) in self.pics.iter().zip(self.ratings.iter()).enumerate() {
+            if *rating > 0.0 {
+                parents.push(pic.clone());
+                ratings.push(*rating);
+                indexes.push(i);
+            }
+        }
+        if parents.is_empty() {
+            println!("rate some pictures with the middle mouse button before breeding");
+            return;
+        }
         let now = Instant::now();
+        let seed = self.rng.gen();
+        self.rng_draws += 1;
+        let children = breed_population(
+            &parents,
+            &ratings,
+            (THUMB_ROWS * THUMB_COLS) as usize,
+            seed,
+            self.video,
+            &self.pic_names(),
+            &self.generation_config,
+        );
+        self.generation += 1;
+        let mut pics = Vec::new();
+        let mut ids = Vec::new();
+        for child in children {
+            // map back from the rated pics to their ids
+            let parents = child
+                .parents
+                .iter()
+                .map(|p| self.ids[indexes[*p]])
+                .collect();
+            ids.push(self.genealogy.add(parents, child.origin, self.generation));
+            let thumbnail = self.render_thumbnail(&child.pic);
+            pics.push((child.pic, thumbnail));
+        }
+        let seeds = vec![None; pics.len()];
+        self.set_population(ctx, pics, ids, seed, seeds);
+        println!("breed seed:{} elapsed:{}", seed, now.elapsed().as_millis());
+    }
+
+    fn set_population(
+        &mut self,
+        ctx: &mut Context,
+        pics: Vec<(Pic, Vec<u8>)>,
+        ids: Vec<u64>,
+        seed: u64,
+        seeds: Vec<Option<u64>>,
+    ) {
+        // todo make this layout code less dumb
         self.img_buttons.clear();
         let width = 1.0 / (THUMB_COLS as f32 * 1.01);
         let height = 1.0 / (THUMB_ROWS as f32 * 1.01);
         let mut y_pct = 0.01;
-        let pic_names = &self.pictures.keys().collect();
+        let mut pic_iter = pics.iter();
         for _ in 0..THUMB_ROWS {
             let mut x_pct = 0.01;
             for _ in 0..THUMB_COLS {
-                let pic_type = self.rng.gen_range(0, 5);
-
-                //let pic_type = 4;
-                let pic = match pic_type {
-                    0 => Pic::new_mono(TREE_MIN, TREE_MAX, false, &mut self.rng, pic_names),
-                    1 => Pic::new_gradient(TREE_MIN, TREE_MAX, false, &mut self.rng, pic_names),
-                    2 => Pic::new_rgb(TREE_MIN, TREE_MAX, false, &mut self.rng, pic_names),
-                    3 => Pic::new_hsv(TREE_MIN, TREE_MAX, false, &mut self.rng, pic_names),
-                    4 => Pic::new_grayscale(TREE_MIN, TREE_MAX, false, &mut self.rng, pic_names),
-                    _ => panic!("invalid"),
-                };
-
+                let (_, thumbnail) = pic_iter.next().unwrap();
                 let img = graphics::Image::from_rgba8(
                     ctx,
-                    256 as u16,
-                    256 as u16,
-                    &pic.get_rgba8::<Avx2>(false, self.pictures.clone(), 256, 256, 0.0)[0..],
+                    THUMB_SIZE as u16,
+                    THUMB_SIZE as u16,
+                    &thumbnail[0..],
                 )
                 .unwrap();
-                self.pics.push(pic);
                 self.img_buttons
                     .push(Button::new(img, x_pct, y_pct, width - 0.01, height - 0.01));
                 x_pct += width;
@@ -135,51 +328,265 @@ impl MainState {
             println!("--------------------");
             y_pct += height;
         }
-        println!("genpop elapsed:{}", now.elapsed().as_millis());
+        self.ratings = vec![0.0; pics.len()];
+        self.pics = pics.into_iter().map(|(pic, _)| pic).collect();
+        self.ids = ids;
+        self.seeds = seeds;
+        self.seed = seed;
+        self.start_thumb_videos();
     }
 
-    fn new(mut ctx: &mut Context) -> GameResult<MainState> {
+    // renders the loops for every thumbnail on one background thread, so the
+    // grid shows up right away and starts moving as they finish
+    fn start_thumb_videos(&mut self) {
+        self.thumb_videos = self
+            .pics
+            .iter()
+            .map(|_| RwArc::new(BackgroundVideo::NotYet))
+            .collect();
+        if !self.video {
+            return;
+        }
+        let pics = self.pics.clone();
+        let videos: Vec<RwArc<BackgroundVideo>> =
+            self.thumb_videos.iter().map(|video| video.clone()).collect();
+        let pictures = self.pictures.clone();
+        thread::spawn(move || {
+            for (pic, video) in pics.iter().zip(videos.iter()) {
+                // the population was replaced, don't bother with the rest
+                if video.is_abandoned() {
+                    return;
+                }
+                let size = VIDEO_THUMB_SIZE;
+                let pictures = pictures.clone();
+                let (viewport, antialias) = (&Viewport::default(), Antialias::Off);
+                // these play over and over, so they always loop
+                let (fps, d) = (VIDEO_FPS, VIDEO_DURATION);
+                let frames =
+                    pic.get_video::<Avx2>(pictures, size, size, viewport, antialias, fps, d, true);
+                video.write(BackgroundVideo::Almost(frames, size, size));
+            }
+        });
+    }
+
+    // which frame of a loop to show so all of them play in sync
+    fn video_frame(&self, frame_count: usize) -> usize {
+        (self.frame_elapsed / VIDEO_DURATION * frame_count as f32) as usize % frame_count
+    }
+
+    // keeps the population and its ratings, still trees just don't move until
+    // breeding or a new population brings in time leaves
+    fn toggle_video(&mut self) {
+        self.video = !self.video;
+        println!("video mode {}", if self.video { "on" } else { "off" });
+        self.start_thumb_videos();
+    }
+
+    // starts the rng from seed and skips the draws already taken from it
+    fn seed_rng(&mut self, seed: u64, draws: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        for _ in 0..draws {
+            let _: u64 = self.rng.gen();
+        }
+        self.rng_seed = seed;
+        self.rng_draws = draws;
+    }
+
+    fn save_session(&self) {
+        let session = Session {
+            generation: self.generation,
+            seed: self.rng_seed,
+            draws: self.rng_draws,
+            population_seed: self.seed,
+            pics: self.pics.clone(),
+            ratings: self.ratings.clone(),
+            ids: self.ids.clone(),
+            genealogy: self.genealogy.clone(),
+        };
+        match save_session(SESSION_FILE, &session) {
+            Ok(()) => println!("saved session to {}", SESSION_FILE),
+            Err(msg) => println!("{}", msg),
+        }
+    }
+
+    fn load_session(
+        &mut self,
+        ctx: &mut Context,
+        session: Session,
+    ) -> std::result::Result<(), String> {
+        let size = (THUMB_ROWS * THUMB_COLS) as usize;
+        if session.pics.len() != size {
+            return Err(format!(
+                "Session has {} pics, expected {}",
+                session.pics.len(),
+                size
+            ));
+        }
+        let pics = session
+            .pics
+            .into_iter()
+            .map(|pic| {
+                let thumbnail = self.render_thumbnail(&pic);
+                (pic, thumbnail)
+            })
+            .collect();
+        self.genealogy = session.genealogy;
+        // only the first generation was made straight from the population seed
+        let seeds = (0..size)
+            .map(|i| {
+                if session.generation == 0 {
+                    Some(individual_seed(session.population_seed, i))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let population_seed = session.population_seed;
+        self.set_population(ctx, pics, session.ids, population_seed, seeds);
+        self.ratings = session.ratings;
+        self.generation = session.generation;
+        self.seed_rng(session.seed, session.draws);
+        Ok(())
+    }
+
+    fn new(
+        mut ctx: &mut Context,
+        generation_config: GenerationConfig,
+        seed: u64,
+        video_settings: VideoSettings,
+    ) -> GameResult<MainState> {
         let imgui_wrapper = ImGuiWrapper::new(&mut ctx);
+        let library = Library::open(LIBRARY_DIR).map_err(GameError::FilesystemError)?;
 
         let s = MainState {
             state: GameState::Select,
             imgui_wrapper,            
             pics: Vec::new(),
+            ratings: Vec::new(),
+            ids: Vec::new(),
+            genealogy: Genealogy::new(),
+            generation: 0,
+            seeds: Vec::new(),
             img_buttons: Vec::new(),
             dt: std::time::Duration::new(0, 0),
             frame_elapsed: 0.0,
-            rng: StdRng::from_rng(rand::thread_rng()).unwrap(),
+            rng: StdRng::seed_from_u64(seed),
+            rng_seed: seed,
+            rng_draws: 0,
+            seed,
+            library,
+            generation_config,
+            degenerate_thresholds: DegenerateThresholds::default(),
             mouse_state: MouseState::Nothing,
             zoom_image: RwArc::new(BackgroundImage::NotYet),
+            video: false,
+            thumb_videos: Vec::new(),
+            zoom_video: RwArc::new(BackgroundVideo::NotYet),
+            zoom_index: 0,
+            zoom_viewport: Viewport::fit(WIDTH, HEIGHT),
+            video_settings,
             pictures: Arc::new(load_pictures(ctx)),
         };
         Ok(s)
     }
 
+    fn add_favorite(&self, i: usize) {
+        let name = format!("generation {} pic {}", self.generation, i);
+        let mut favorite = Favorite::new(&name, self.pics[i].clone(), self.seeds[i]);
+        if let Some(record) = self.genealogy.get(self.ids[i]) {
+            favorite.genealogy = self.genealogy.id;
+            favorite.parents = record.parents.clone();
+        }
+        let thumbnail = self.render_thumbnail(&self.pics[i]);
+        match self.library.add(&favorite, &thumbnail, THUMB_SIZE, THUMB_SIZE) {
+            Ok(favorite) => println!("added {} to the library as {}", name, favorite.id),
+            Err(msg) => println!("{}", msg),
+        }
+    }
+
     fn update_select(&mut self, ctx: &mut Context) {
         for (i, img_button) in self.img_buttons.iter().enumerate() {
             if img_button.left_clicked(ctx, &self.mouse_state) {
-                println!("{}", self.pics[i].to_lisp());
-                println!("button left clicked");
+                self.add_favorite(i);
                 break;
             }
             if img_button.right_clicked(ctx, &self.mouse_state) {
                 println!("button right clicked");
-                let pic = self.pics[i].clone();
-                let arc = self.zoom_image.clone();
-                let pics = self.pictures.clone();
-                thread::spawn(move || {
-                    println!("create image");
-                    let img_data = pic.get_rgba8::<Avx2>(true, pics, WIDTH, HEIGHT, 0.0);
-                    arc.write(BackgroundImage::Almost(img_data));
-                });
                 self.state = GameState::Zoom;
+                self.zoom_index = i;
+                self.zoom_viewport = Viewport::fit(WIDTH, HEIGHT);
+                self.render_zoom();
                 break;
             }
+            if img_button.middle_clicked(ctx, &self.mouse_state) {
+                self.ratings[i] = if self.ratings[i] > 0.0 { 0.0 } else { 1.0 };
+                break;
+            }
+        }
+    }
+
+    // renders the zoom view of the pic at zoom_index in the background, any
+    // render still going for an older view is left to finish unseen
+    fn render_zoom(&mut self) {
+        let pic = self.pics[self.zoom_index].clone();
+        let pics = self.pictures.clone();
+        let viewport = self.zoom_viewport;
+        let antialias = self.video_settings.antialias;
+        self.zoom_image = RwArc::new(BackgroundImage::NotYet);
+        let arc = self.zoom_image.clone();
+        if self.video {
+            self.zoom_video = RwArc::new(BackgroundVideo::NotYet);
+            let video = self.zoom_video.clone();
+            thread::spawn(move || {
+                let (w, h) = (WIDTH / VIDEO_ZOOM_SCALE, HEIGHT / VIDEO_ZOOM_SCALE);
+                let (fps, d) = (VIDEO_FPS, VIDEO_DURATION);
+                let antialias = Antialias::Off;
+                let frames =
+                    pic.get_video::<Avx2>(pics, w, h, &viewport, antialias, fps, d, true);
+                video.write(BackgroundVideo::Almost(frames, w, h));
+            });
+            // the loop is all that gets shown, so don't render a still as well
+            return;
         }
+        thread::spawn(move || {
+            println!("create image");
+            let time = Time::linear(0.0);
+            let img_data =
+                pic.get_rgba8::<Avx2>(true, pics, WIDTH, HEIGHT, &viewport, antialias, time);
+            arc.write(BackgroundImage::Almost(img_data));
+        });
+    }
+
+    // zooms the zoom view about the mouse, wheel is notches up
+    fn zoom_wheel(&mut self, ctx: &Context, wheel: f32) {
+        let mouse = ggez::input::mouse::position(ctx);
+        let u = mouse.x / WIDTH as f32 * 2.0 - 1.0;
+        let v = mouse.y / HEIGHT as f32 * 2.0 - 1.0;
+        self.zoom_viewport = self.zoom_viewport.zoom(WHEEL_ZOOM.powf(wheel), u, v);
+        self.render_zoom();
+    }
+
+    // renders in the background since videos take a while
+    fn export_video(&self) {
+        let pic = self.pics[self.zoom_index].clone();
+        let pics = self.pictures.clone();
+        let mut settings = self.video_settings;
+        // export what the zoom view shows, fitted to the export size
+        settings.viewport = Viewport {
+            aspect: settings.width as f32 / settings.height as f32,
+            ..self.zoom_viewport
+        };
+        thread::spawn(move || {
+            let path = settings.format.default_path();
+            match export_video::<Avx2>(&pic, pics, &settings, path) {
+                Ok(()) => println!("wrote video to {}", path),
+                Err(msg) => println!("{}", msg),
+            }
+        });
     }
 
     fn update_zoom(&mut self, ctx: &mut Context) {
+        BackgroundVideo::complete(&self.zoom_video, ctx);
         let maybe_img = match &*self.zoom_image.read() {
             BackgroundImage::NotYet => None,
             BackgroundImage::Almost(data) => {
@@ -203,24 +610,48 @@ impl MainState {
             if img_button.right_clicked(ctx, &self.mouse_state) {
                 println!("button right clicked");
                 self.zoom_image.write(BackgroundImage::NotYet);
+                self.zoom_video = RwArc::new(BackgroundVideo::NotYet);
                 self.state = GameState::Select;
             }
         }
     }
 
     fn draw_select(&mut self, ctx: &mut Context) {
-        for img_button in &self.img_buttons {
-            img_button.draw(ctx);
+        for (i, img_button) in self.img_buttons.iter().enumerate() {
+            match self.thumb_videos.get(i).map(|video| video.read()) {
+                Some(video) => match &*video {
+                    BackgroundVideo::Complete(frames) if !frames.is_empty() => {
+                        img_button.draw_image(ctx, &frames[self.video_frame(frames.len())])
+                    }
+                    _ => img_button.draw(ctx),
+                },
+                None => img_button.draw(ctx),
+            }
+            if self.ratings[i] > 0.0 {
+                img_button.draw_highlight(ctx);
+            }
         }
         // Render game ui
         {
             let window = ggez::graphics::window(ctx);
 
-            self.imgui_wrapper.render(ctx,window.get_hidpi_factor() as f32);
+            let hidpi_factor = window.get_hidpi_factor() as f32;
+            if let Some(seed) = self.imgui_wrapper.render(ctx, hidpi_factor, self.seed) {
+                self.seed_rng(seed, 0);
+                self.gen_population(ctx, seed);
+            }
         }
     }
 
     fn draw_zoom(&self, ctx: &mut Context) {
+        if let BackgroundVideo::Complete(frames) = &*self.zoom_video.read() {
+            if !frames.is_empty() {
+                let scale = VIDEO_ZOOM_SCALE as f32;
+                let params = graphics::DrawParam::new().scale([scale, scale]);
+                let _ = graphics::draw(ctx, &frames[self.video_frame(frames.len())], params);
+                return;
+            }
+        }
         match &*self.zoom_image.read() {
             BackgroundImage::NotYet => (),
             BackgroundImage::Almost(_) => (),
@@ -235,7 +666,12 @@ impl EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         self.dt = timer::delta(ctx);
         match self.state {
-            GameState::Select => self.update_select(ctx),
+            GameState::Select => {
+                for video in &self.thumb_videos {
+                    BackgroundVideo::complete(video, ctx);
+                }
+                self.update_select(ctx)
+            }
             GameState::Zoom => self.update_zoom(ctx),
         }
         self.frame_elapsed = (self.frame_elapsed + self.dt.as_millis() as f32) % VIDEO_DURATION;
@@ -273,6 +709,14 @@ impl EventHandler for MainState {
         ));
     }
 
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        self.imgui_wrapper.update_mouse_wheel(y);
+        match self.state {
+            GameState::Select => (),
+            GameState::Zoom => self.zoom_wheel(ctx, y),
+        }
+    }
+
     fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
         self.mouse_state = MouseState::Up(MouseButtonState {
             which_button: button,
@@ -284,13 +728,46 @@ impl EventHandler for MainState {
 
     fn key_down_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         keycode: KeyCode,
         _keymods: KeyMods,
         _repeat: bool,
     ) {
         match keycode {
             KeyCode::P => (),
+            KeyCode::B => match self.state {
+                GameState::Select => self.breed(ctx),
+                GameState::Zoom => (),
+            },
+            KeyCode::T => match self.state {
+                GameState::Select => self.toggle_video(),
+                GameState::Zoom => (),
+            },
+            KeyCode::V => match self.state {
+                GameState::Select => (),
+                GameState::Zoom => self.export_video(),
+            },
+            KeyCode::S => self.save_session(),
+            KeyCode::D => {
+                // the zoomed pic's ancestors, or everything from the grid
+                let dot = match self.state {
+                    GameState::Select => self.genealogy.to_dot(),
+                    GameState::Zoom => self.genealogy.lineage_to_dot(self.ids[self.zoom_index]),
+                };
+                match fs::write(LINEAGE_FILE, dot) {
+                    Ok(()) => println!("wrote lineage to {}", LINEAGE_FILE),
+                    Err(e) => println!("Unable to write lineage {}: {}", LINEAGE_FILE, e),
+                }
+            }
+            KeyCode::L => match self.state {
+                GameState::Select => {
+                    match load_session(SESSION_FILE).and_then(|s| self.load_session(ctx, s)) {
+                        Ok(()) => println!("loaded session from {}", SESSION_FILE),
+                        Err(msg) => println!("{}", msg),
+                    }
+                }
+                GameState::Zoom => (),
+            },
             _ => (),
         }
     }
@@ -316,6 +793,86 @@ pub fn load_pictures(ctx: &mut Context) -> HashMap<String, ActualPicture> {
     pictures
 }
 
+// reads the pictures directory with the image crate instead of ggez, for
+// rendering without a window
+fn load_pictures_headless(pic_path: &Path) -> HashMap<String, ActualPicture> {
+    let mut pictures = HashMap::new();
+    if let Ok(files) = fs::read_dir(pic_path) {
+        for file in files {
+            let path = file.unwrap().path();
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            match image::open(&path) {
+                Ok(img) => {
+                    let img = img.to_rgba();
+                    let (w, h) = (img.width() as u16, img.height() as u16);
+                    pictures.insert(name.clone(), ActualPicture::from_rgba8(&img, w, h, name));
+                }
+                Err(e) => println!("Unable to load {}: {}", path.display(), e),
+            }
+        }
+    }
+    pictures
+}
+
+// the first picture node under node that isn't one of the loaded pictures
+fn missing_picture(node: &APTNode, pictures: &HashMap<String, ActualPicture>) -> Option<String> {
+    match node {
+        APTNode::Picture(name, _) if !pictures.contains_key(name) => Some(name.clone()),
+        _ => node
+            .get_children()?
+            .iter()
+            .find_map(|child| missing_picture(child, pictures)),
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> T {
+    match value.parse::<T>() {
+        Ok(v) => v,
+        Err(_) => panic!("Unable to parse {} {}", name, value),
+    }
+}
+
+// renders a pic from a lisp file without opening a window
+fn render_headless(
+    pic_file: &str,
+    pictures_dir: &Path,
+    out: &str,
+    video: bool,
+    depth: u8,
+    range: OutOfRange,
+    settings: &VideoSettings,
+) -> std::result::Result<(), String> {
+    let code = fs::read_to_string(pic_file)
+        .map_err(|e| format!("Unable to read {}: {}", pic_file, e))?;
+    let pic = lisp_to_pic(code)?;
+    let pictures = load_pictures_headless(pictures_dir);
+    for tree in pic.trees() {
+        if let Some(name) = missing_picture(tree, &pictures) {
+            let msg = format!("{} uses Pic-{}, which isn't in", pic_file, name);
+            return Err(format!("{} {}", msg, pictures_dir.display()));
+        }
+    }
+    let pictures = Arc::new(pictures);
+    let (w, h, viewport) = (settings.width, settings.height, &settings.viewport);
+    if video {
+        export_video::<Avx2>(&pic, pictures, settings, out)
+    } else if depth > 8 {
+        let (antialias, time) = (settings.antialias, Time::linear(0.0));
+        let rgba = pic.get_rgbaf32::<Avx2>(true, pictures, w, h, viewport, antialias, time);
+        if depth == 16 {
+            write_png16(&rgba, w, h, range, out)
+        } else {
+            write_pfm(&rgba, w, h, out)
+        }
+    } else {
+        let antialias = settings.antialias;
+        let rgba =
+            pic.get_rgba8::<Avx2>(true, pictures, w, h, viewport, antialias, Time::linear(0.0));
+        image::save_buffer(out, &rgba, w as u32, h as u32, image::ColorType::RGBA(8))
+            .map_err(|e| format!("Unable to write {}: {}", out, e))
+    }
+}
+
 pub fn main() -> ggez::GameResult {
     match rayon::ThreadPoolBuilder::new()
         .num_threads(0)
@@ -325,6 +882,132 @@ pub fn main() -> ggez::GameResult {
         Err(x) => panic!("{}", x),
     }
 
+    // pass --generation <file> to generate trees in a different style
+    // and --session <file> to carry on from a saved session
+    // and --seed <n> to make the same population someone else saw.
+    // --fps <n> --duration <ms> --size <w>x<h> --format png|gif|apng set up video
+    // export, --loop makes T and T2 go all the way round a circle instead of
+    // half way so videos loop seamlessly,
+    // --viewport <x>,<y>,<scale>,<rotation> picks the part of the plane to render
+    // and --fit widens it to the aspect of --size instead of stretching,
+    // --antialias <n> takes n by n samples a pixel and --adaptive <threshold>
+    // only takes them where neighbouring pixels differ by more than threshold,
+    // in the thumbnails and zoom view as well as renders,
+    // --render <file> --out <path> [--video] renders without a window,
+    // --depth 16 makes that a 16 bit png with --range clamp|wrap|tonemap deciding
+    // what happens to overshoots and --depth 32 a float pfm that keeps them,
+    // and --calibrate measures the raw range of every noise operator
+    let mut generation_config = GenerationConfig::default();
+    let mut session = None;
+    let mut seed = rand::thread_rng().gen();
+    let mut video_settings = VideoSettings::default();
+    let mut render = None;
+    let mut out = None;
+    let mut video = false;
+    let mut calibrate = false;
+    let mut fit = false;
+    let mut samples = None;
+    let mut threshold = None;
+    let mut depth = 8;
+    let mut range = OutOfRange::Clamp;
+    let args: Vec<String> = env::args().collect();
+    for i in 1..args.len() {
+        if args[i] == "--generation" && i + 1 < args.len() {
+            generation_config = match load_generation_config(&args[i + 1]) {
+                Ok(config) => config,
+                Err(msg) => panic!("{}", msg),
+            };
+        }
+        if args[i] == "--seed" && i + 1 < args.len() {
+            seed = parse_arg("seed", &args[i + 1]);
+        }
+        if args[i] == "--fps" && i + 1 < args.len() {
+            video_settings.fps = parse_arg("fps", &args[i + 1]);
+        }
+        if args[i] == "--duration" && i + 1 < args.len() {
+            video_settings.duration = parse_arg("duration", &args[i + 1]);
+        }
+        if args[i] == "--size" && i + 1 < args.len() {
+            let size: Vec<&str> = args[i + 1].split('x').collect();
+            if size.len() != 2 {
+                panic!("Expected a size like 640x480, found {}", args[i + 1]);
+            }
+            video_settings.width = parse_arg("width", size[0]);
+            video_settings.height = parse_arg("height", size[1]);
+        }
+        if args[i] == "--format" && i + 1 < args.len() {
+            video_settings.format = match VideoFormat::from_name(&args[i + 1]) {
+                Some(format) => format,
+                None => panic!("Unknown video format {}", args[i + 1]),
+            };
+        }
+        if args[i] == "--render" && i + 1 < args.len() {
+            render = Some(args[i + 1].clone());
+        }
+        if args[i] == "--out" && i + 1 < args.len() {
+            out = Some(args[i + 1].clone());
+        }
+        if args[i] == "--video" {
+            video = true;
+        }
+        if args[i] == "--calibrate" {
+            calibrate = true;
+        }
+        if args[i] == "--loop" {
+            video_settings.looping = true;
+        }
+        if args[i] == "--viewport" && i + 1 < args.len() {
+            video_settings.viewport = match parse_viewport(&args[i + 1]) {
+                Ok(viewport) => viewport,
+                Err(msg) => panic!("{}", msg),
+            };
+        }
+        if args[i] == "--fit" {
+            fit = true;
+        }
+        if args[i] == "--depth" && i + 1 < args.len() {
+            depth = parse_arg("depth", &args[i + 1]);
+            if depth != 8 && depth != 16 && depth != 32 {
+                panic!("Expected a depth of 8, 16 or 32, found {}", depth);
+            }
+        }
+        if args[i] == "--range" && i + 1 < args.len() {
+            range = match OutOfRange::from_name(&args[i + 1]) {
+                Some(range) => range,
+                None => panic!("Unknown out of range policy {}", args[i + 1]),
+            };
+        }
+        if args[i] == "--antialias" && i + 1 < args.len() {
+            samples = Some(parse_arg("antialias", &args[i + 1]));
+        }
+        if args[i] == "--adaptive" && i + 1 < args.len() {
+            threshold = Some(parse_arg("adaptive", &args[i + 1]));
+        }
+        if args[i] == "--session" && i + 1 < args.len() {
+            session = match load_session(&args[i + 1]) {
+                Ok(session) => Some(session),
+                Err(msg) => panic!("{}", msg),
+            };
+        }
+    }
+
+    if fit {
+        let (w, h) = (video_settings.width, video_settings.height);
+        video_settings.viewport.aspect = w as f32 / h as f32;
+    }
+    video_settings.antialias = match (samples, threshold) {
+        (None, None) => Antialias::Off,
+        (Some(n), None) => Antialias::Grid(n),
+        (n, Some(threshold)) => Antialias::Adaptive(n.unwrap_or(ADAPTIVE_SAMPLES), threshold),
+    };
+
+    if calibrate {
+        for (name, range) in calibration_table(CALIBRATION_SAMPLES, seed) {
+            println!("{}: {} to {}", name, range.min, range.max);
+        }
+        return Ok(());
+    }
+
     let pictures_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(manifest_dir);
         path.push("pictures");
@@ -333,6 +1016,27 @@ pub fn main() -> ggez::GameResult {
         path::PathBuf::from("./pictures")
     };
 
+    if let Some(pic_file) = render {
+        let out = match out {
+            Some(out) => out,
+            None if video => video_settings.format.default_path().to_string(),
+            None => "pic.png".to_string(),
+        };
+        match render_headless(
+            &pic_file,
+            &pictures_dir,
+            &out,
+            video,
+            depth,
+            range,
+            &video_settings,
+        ) {
+            Ok(()) => println!("wrote {}", out),
+            Err(msg) => panic!("{}", msg),
+        }
+        return Ok(());
+    }
+
     /*  let hidpi_factor: f32;
     {
         // Create a dummy window so we can get monitor scaling information
@@ -350,7 +1054,14 @@ pub fn main() -> ggez::GameResult {
         );
     let (ref mut ctx, event_loop) = &mut cb.build()?;
 
-    let state = &mut MainState::new(ctx).unwrap();
-    state.gen_population(ctx);
+    let state = &mut MainState::new(ctx, generation_config, seed, video_settings).unwrap();
+    match session {
+        Some(session) => {
+            if let Err(msg) = state.load_session(ctx, session) {
+                panic!("{}", msg);
+            }
+        }
+        None => state.gen_population(ctx, seed),
+    }
     event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,591 @@
+use crate::operator::*;
+use crate::parser::*;
+use crate::pic::*;
+use rand::prelude::*;
+use simdeez::scalar::*;
+use simdeez::*;
+use simdnoise::*;
+use std::sync::mpsc::*;
+
+// t only runs from -1 to 1 over a whole video, so the 3D noise operators keep
+// its frequency low enough for the noise to drift rather than flicker
+const TIME_FREQUENCY: f32 = 2.0;
+const MAX_OCTAVES: u8 = 6;
+const MAX_SEED: i32 = 65536;
+// how far Warp pushes coordinates at an amount of 1, raw fbm is only around
+// +-0.03 so this comes out at a few noise cells
+const WARP_STRENGTH: f32 = 100.0;
+// 1 in this many mutations of a noise node tweaks its settings instead of
+// replacing the subtree
+pub const NOISE_MUTATION_CHANCE: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseKind {
+    /// Octaves and seed
+    Simplex,
+    /// Octaves and seed, on a lattice of random values instead of gradients
+    Value,
+    /// Seed, distance and return type
+    Cellular,
+    /// Seed and distance, always the gap between the nearest two cells
+    Edges,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DistanceFunction {
+    Euclidean,
+    Manhattan,
+    Natural,
+}
+
+const DISTANCE_FUNCTIONS: &[DistanceFunction] = &[
+    DistanceFunction::Euclidean,
+    DistanceFunction::Manhattan,
+    DistanceFunction::Natural,
+];
+
+impl DistanceFunction {
+    pub fn name(self) -> &'static str {
+        match self {
+            DistanceFunction::Euclidean => "Euclidean",
+            DistanceFunction::Manhattan => "Manhattan",
+            DistanceFunction::Natural => "Natural",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<DistanceFunction> {
+        DISTANCE_FUNCTIONS
+            .iter()
+            .find(|f| f.name().to_lowercase() == s.to_lowercase())
+            .map(|f| *f)
+    }
+
+    fn to_simdnoise(self) -> CellDistanceFunction {
+        match self {
+            DistanceFunction::Euclidean => CellDistanceFunction::Euclidean,
+            DistanceFunction::Manhattan => CellDistanceFunction::Manhattan,
+            DistanceFunction::Natural => CellDistanceFunction::Natural,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReturnType {
+    /// The distance to the nearest cell, a gradient towards the edges
+    Distance,
+    /// A solid value for each cell
+    CellValue,
+}
+
+impl ReturnType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReturnType::Distance => "Distance",
+            ReturnType::CellValue => "CellValue",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<ReturnType> {
+        match &s.to_lowercase()[..] {
+            "distance" => Some(ReturnType::Distance),
+            "cellvalue" => Some(ReturnType::CellValue),
+            _ => None,
+        }
+    }
+
+    fn to_simdnoise(self) -> CellReturnType {
+        match self {
+            ReturnType::Distance => CellReturnType::Distance,
+            ReturnType::CellValue => CellReturnType::CellValue,
+        }
+    }
+}
+
+/// The settings a noise node carries besides its children, which ones depend on
+/// the operator's NoiseKind. In lisp they follow the children:
+///
+/// ( FBM X Y 0.5 0.5 0.5 0.5 ( Octaves 3 ) ( Seed 3 ) )
+/// ( Cell1 X Y 0.5 0.5 0.5 ( Seed 1 ) ( Distance Manhattan ) ( Return Distance ) )
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NoiseParams {
+    pub octaves: u8,
+    pub seed: i32,
+    pub distance: DistanceFunction,
+    pub return_type: ReturnType,
+}
+
+impl NoiseParams {
+    /// The fixed settings op used before nodes had their own, so older pictures
+    /// keep the same noise fields. Their output is rescaled to -1..1 now, so
+    /// they still render differently
+    pub fn new(op: Operator) -> NoiseParams {
+        NoiseParams {
+            octaves: 3,
+            seed: match op.noise_kind() {
+                Some(NoiseKind::Cellular) | Some(NoiseKind::Edges) => 1,
+                _ => 3,
+            },
+            distance: DistanceFunction::Euclidean,
+            return_type: match op {
+                Operator::Cell2 | Operator::Cell3D2 => ReturnType::CellValue,
+                _ => ReturnType::Distance,
+            },
+        }
+    }
+
+    /// A fresh noise field for op, the return type stays op's own
+    pub fn random(op: Operator, rng: &mut StdRng) -> NoiseParams {
+        NoiseParams {
+            octaves: rng.gen_range(1, MAX_OCTAVES + 1),
+            seed: rng.gen_range(0, MAX_SEED),
+            distance: DISTANCE_FUNCTIONS[rng.gen_range(0, DISTANCE_FUNCTIONS.len())],
+            ..NoiseParams::new(op)
+        }
+    }
+
+    /// Changes one of the settings op uses
+    pub fn mutate(&self, op: Operator, rng: &mut StdRng) -> NoiseParams {
+        let mut params = *self;
+        match (op.noise_kind(), rng.gen_range(0, 3)) {
+            (Some(NoiseKind::Simplex), 0) | (Some(NoiseKind::Value), 0) => {
+                params.octaves = rng.gen_range(1, MAX_OCTAVES + 1);
+            }
+            (Some(NoiseKind::Cellular), 0) | (Some(NoiseKind::Edges), 0) => {
+                params.distance = DISTANCE_FUNCTIONS[rng.gen_range(0, DISTANCE_FUNCTIONS.len())];
+            }
+            (Some(NoiseKind::Cellular), 1) => {
+                params.return_type = match params.return_type {
+                    ReturnType::Distance => ReturnType::CellValue,
+                    ReturnType::CellValue => ReturnType::Distance,
+                };
+            }
+            _ => params.seed = rng.gen_range(0, MAX_SEED),
+        }
+        params
+    }
+
+    /// The settings op uses, ready to go after its children
+    pub fn to_lisp(&self, op: Operator) -> String {
+        match op.noise_kind() {
+            Some(NoiseKind::Simplex) | Some(NoiseKind::Value) => {
+                format!("( Octaves {} ) ( Seed {} )", self.octaves, self.seed)
+            }
+            Some(NoiseKind::Cellular) => format!(
+                "( Seed {} ) ( Distance {} ) ( Return {} )",
+                self.seed,
+                self.distance.name(),
+                self.return_type.name()
+            ),
+            Some(NoiseKind::Edges) => {
+                format!(
+                    "( Seed {} ) ( Distance {} )",
+                    self.seed,
+                    self.distance.name()
+                )
+            }
+            None => String::new(),
+        }
+    }
+}
+
+/// Reads settings up to and including the close paren of the noise node,
+/// anything left out keeps the value from NoiseParams::new
+pub fn parse_noise_params(op: Operator, receiver: &Receiver<Token>) -> Result<NoiseParams, String> {
+    let mut params = NoiseParams::new(op);
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => return Ok(params),
+            Token::OpenParen(_) => (),
+            _ => {
+                return Err(format!(
+                    "Expected noise settings or ')' on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+        let setting = expect_operations(vec!["octaves", "seed", "distance", "return"], receiver)?;
+        match &setting.to_lowercase()[..] {
+            "octaves" => params.octaves = expect_integer(receiver)?.max(1).min(255) as u8,
+            "seed" => params.seed = expect_integer(receiver)? as i32,
+            _ => {
+                let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+                match token {
+                    Token::Operation(s, line_number) if setting.to_lowercase() == "distance" => {
+                        params.distance = DistanceFunction::from_name(s).ok_or_else(|| {
+                            format!("Unknown distance function {} on line {}", s, line_number)
+                        })?
+                    }
+                    Token::Operation(s, line_number) => {
+                        params.return_type = ReturnType::from_name(s).ok_or_else(|| {
+                            format!("Unknown return type {} on line {}", s, line_number)
+                        })?
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Expected a name on line {}",
+                            extract_line_number(&token)
+                        ))
+                    }
+                }
+            }
+        }
+        expect_close_paren(receiver)?;
+    }
+}
+
+/// Evaluates a noise operator with a node's settings, used for constant folding
+pub fn noise_scalar(op: Operator, args: &[f32], params: &NoiseParams) -> f32 {
+    unsafe {
+        let lanes: Vec<<Scalar as Simd>::Vf32> =
+            args.iter().map(|arg| Scalar::set1_ps(*arg)).collect();
+        noise_simd::<Scalar>(op, &lanes[..], params)[0]
+    }
+}
+
+/// Evaluates a noise operator with a node's settings, args are in child order.
+/// The result is stretched from the operator's calibrated range to -1..1
+#[inline(always)]
+pub unsafe fn noise_simd<S: Simd>(op: Operator, a: &[S::Vf32], params: &NoiseParams) -> S::Vf32 {
+    let v = scaled_noise_simd::<S>(op, a, params);
+    // only a safety net, the tests check the ranges hold before this
+    S::max_ps(S::min_ps(v, S::set1_ps(1.0)), S::set1_ps(-1.0))
+}
+
+/// noise_simd before it is clamped to -1..1
+#[inline(always)]
+pub unsafe fn scaled_noise_simd<S: Simd>(
+    op: Operator,
+    a: &[S::Vf32],
+    params: &NoiseParams,
+) -> S::Vf32 {
+    let range = noise_range(op, params);
+    let v = raw_noise_simd::<S>(op, a, params);
+    (v - S::set1_ps(range.min)) * S::set1_ps(2.0 / (range.max - range.min)) - S::set1_ps(1.0)
+}
+
+/// What the noise libraries return before calibration
+#[inline(always)]
+pub unsafe fn raw_noise_simd<S: Simd>(
+    op: Operator,
+    a: &[S::Vf32],
+    params: &NoiseParams,
+) -> S::Vf32 {
+    let (octaves, seed) = (params.octaves, params.seed);
+    match op {
+        // ( FBM x y xfreq yfreq lacunarity gain ), likewise Ridge and Turbulence
+        Operator::FBM | Operator::Ridge | Operator::Turbulence => {
+            let x = a[0] * (a[2] * S::set1_ps(15.0));
+            let y = a[1] * (a[3] * S::set1_ps(15.0));
+            let lacunarity = a[4] * S::set1_ps(5.0);
+            let gain = a[5] * S::set1_ps(0.5);
+            match op {
+                Operator::FBM => simplex::fbm_2d::<S>(x, y, lacunarity, gain, octaves, seed),
+                Operator::Ridge => simplex::ridge_2d::<S>(x, y, lacunarity, gain, octaves, seed),
+                _ => simplex::turbulence_2d::<S>(x, y, lacunarity, gain, octaves, seed),
+            }
+        }
+        // ( FBM3D x y t xfreq yfreq tfreq lacunarity gain ), likewise the other 3D ones
+        Operator::FBM3D | Operator::Ridge3D | Operator::Turbulence3D => {
+            let x = a[0] * (a[3] * S::set1_ps(15.0));
+            let y = a[1] * (a[4] * S::set1_ps(15.0));
+            let t = a[2] * (a[5] * S::set1_ps(TIME_FREQUENCY));
+            let lacunarity = a[6] * S::set1_ps(5.0);
+            let gain = a[7] * S::set1_ps(0.5);
+            match op {
+                Operator::FBM3D => simplex::fbm_3d::<S>(x, y, t, lacunarity, gain, octaves, seed),
+                Operator::Ridge3D => {
+                    simplex::ridge_3d::<S>(x, y, t, lacunarity, gain, octaves, seed)
+                }
+                _ => simplex::turbulence_3d::<S>(x, y, t, lacunarity, gain, octaves, seed),
+            }
+        }
+        // ( Cell1 x y xfreq yfreq jitter ), likewise Cell2
+        Operator::Cell1 | Operator::Cell2 => cellular::cellular_2d::<S>(
+            a[0] * (a[2] * S::set1_ps(4.0)),
+            a[1] * (a[3] * S::set1_ps(4.0)),
+            params.distance.to_simdnoise(),
+            params.return_type.to_simdnoise(),
+            a[4] * S::set1_ps(0.5),
+            seed,
+        ),
+        // ( Cell3D1 x y t xfreq yfreq tfreq jitter ), likewise Cell3D2
+        Operator::Cell3D1 | Operator::Cell3D2 => cellular::cellular_3d::<S>(
+            a[0] * (a[3] * S::set1_ps(4.0)),
+            a[1] * (a[4] * S::set1_ps(4.0)),
+            a[2] * (a[5] * S::set1_ps(TIME_FREQUENCY)),
+            params.distance.to_simdnoise(),
+            params.return_type.to_simdnoise(),
+            a[6] * S::set1_ps(0.5),
+            seed,
+        ),
+        // ( CellEdges x y xfreq yfreq jitter )
+        Operator::CellEdges => {
+            // simdnoise gives nearest minus second nearest, flip it so edges are 0
+            S::setzero_ps()
+                - cellular::cellular2_2d::<S>(
+                    a[0] * (a[2] * S::set1_ps(4.0)),
+                    a[1] * (a[3] * S::set1_ps(4.0)),
+                    params.distance.to_simdnoise(),
+                    Cell2ReturnType::Distance2Sub,
+                    a[4] * S::set1_ps(0.5),
+                    0,
+                    1,
+                    seed,
+                )
+        }
+        // ( Value x y xfreq yfreq lacunarity gain )
+        Operator::Value => value_fbm_2d::<S>(
+            a[0] * (a[2] * S::set1_ps(15.0)),
+            a[1] * (a[3] * S::set1_ps(15.0)),
+            a[4] * S::set1_ps(5.0),
+            a[5] * S::set1_ps(0.5),
+            octaves,
+            seed,
+        ),
+        // ( Warp x y xfreq yfreq amount )
+        Operator::Warp => {
+            let x = a[0] * (a[2] * S::set1_ps(15.0));
+            let y = a[1] * (a[3] * S::set1_ps(15.0));
+            let (lacunarity, gain) = (S::set1_ps(2.0), S::set1_ps(0.5));
+            // two more fbm fields, offset so they don't match, push the coordinates around
+            let qx = simplex::fbm_2d::<S>(x, y, lacunarity, gain, octaves, seed);
+            let qy = simplex::fbm_2d::<S>(
+                x + S::set1_ps(5.2),
+                y + S::set1_ps(1.3),
+                lacunarity,
+                gain,
+                octaves,
+                seed,
+            );
+            let amount = a[4] * S::set1_ps(WARP_STRENGTH);
+            simplex::fbm_2d::<S>(
+                x + qx * amount,
+                y + qy * amount,
+                lacunarity,
+                gain,
+                octaves,
+                seed,
+            )
+        }
+        _ => op.simd::<S>(a),
+    }
+}
+
+// A random value from -1 to 1 for each lattice point. Every product is of a
+// 16 bit value and a 15 bit constant, the scalar backend adds and multiplies
+// with plain i32 arithmetic and would panic on overflow in debug builds
+#[inline(always)]
+unsafe fn lattice_value<S: Simd>(x: S::Vi32, y: S::Vi32, seed: S::Vi32) -> S::Vf32 {
+    let low = S::set1_epi32(0xffff);
+    let mix = |h: S::Vi32, k: i32| S::mullo_epi32(S::and_epi32(h, low), S::set1_epi32(k));
+    let mut h = mix(x, 27_459);
+    h = S::xor_epi32(h, mix(y, 20_251));
+    h = S::xor_epi32(h, mix(seed, 11_601));
+    h = mix(S::xor_epi32(h, S::srli_epi32(h, 15)), 23_497);
+    h = mix(S::xor_epi32(h, S::srli_epi32(h, 13)), 30_781);
+    h = S::xor_epi32(h, S::srli_epi32(h, 16));
+    let v = S::cvtepi32_ps(S::and_epi32(h, low));
+    v * S::set1_ps(2.0 / 65535.0) - S::set1_ps(1.0)
+}
+
+/// Smoothly interpolated random values on the integer lattice, -1 to 1
+#[inline(always)]
+pub unsafe fn value_2d<S: Simd>(x: S::Vf32, y: S::Vf32, seed: i32) -> S::Vf32 {
+    let x0 = S::floor_ps(x);
+    let y0 = S::floor_ps(y);
+    // cvtps_epi32 saturates far from the origin, so the lattice coordinates
+    // are cut to the 16 bits lattice_value hashes before one is added to them
+    let low = S::set1_epi32(0xffff);
+    let xi = S::and_epi32(S::cvtps_epi32(x0), low);
+    let yi = S::and_epi32(S::cvtps_epi32(y0), low);
+    let one = S::set1_epi32(1);
+    let seed = S::set1_epi32(seed);
+    let smooth = |t: S::Vf32| t * t * (S::set1_ps(3.0) - S::set1_ps(2.0) * t);
+    let u = smooth(x - x0);
+    let v = smooth(y - y0);
+    let v00 = lattice_value::<S>(xi, yi, seed);
+    let v10 = lattice_value::<S>(xi + one, yi, seed);
+    let v01 = lattice_value::<S>(xi, yi + one, seed);
+    let v11 = lattice_value::<S>(xi + one, yi + one, seed);
+    let bottom = v00 + (v10 - v00) * u;
+    let top = v01 + (v11 - v01) * u;
+    bottom + (top - bottom) * v
+}
+
+/// Octaves of value noise summed like simplex::fbm_2d
+#[inline(always)]
+pub unsafe fn value_fbm_2d<S: Simd>(
+    mut x: S::Vf32,
+    mut y: S::Vf32,
+    lacunarity: S::Vf32,
+    gain: S::Vf32,
+    octaves: u8,
+    seed: i32,
+) -> S::Vf32 {
+    let mut amplitude = S::set1_ps(1.0);
+    let mut result = value_2d::<S>(x, y, seed);
+    for octave in 1..octaves as i32 {
+        x = x * lacunarity;
+        y = y * lacunarity;
+        amplitude = amplitude * gain;
+        result = result + value_2d::<S>(x, y, seed.wrapping_add(octave)) * amplitude;
+    }
+    result
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NoiseRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Samples op's raw output over random inputs and settings, keeping the
+/// distance function and return type fixed since they change the range most
+pub fn calibrate(
+    op: Operator,
+    distance: DistanceFunction,
+    return_type: ReturnType,
+    samples: usize,
+    rng: &mut StdRng,
+) -> NoiseRange {
+    let mut range = NoiseRange {
+        min: std::f32::MAX,
+        max: std::f32::MIN,
+    };
+    for _ in 0..samples {
+        let params = NoiseParams {
+            distance,
+            return_type,
+            ..NoiseParams::random(op, rng)
+        };
+        let args: Vec<<Scalar as Simd>::Vf32> = (0..op.arity())
+            .map(|_| unsafe { Scalar::set1_ps(rng.gen_range(-1.0, 1.0)) })
+            .collect();
+        let v = unsafe { raw_noise_simd::<Scalar>(op, &args[..], &params)[0] };
+        if v.is_finite() {
+            range.min = range.min.min(v);
+            range.max = range.max.max(v);
+        }
+    }
+    range
+}
+
+/// Every range noise_range needs, measured with samples samples each
+pub fn calibration_table(samples: usize, seed: u64) -> Vec<(String, NoiseRange)> {
+    let rng = &mut StdRng::seed_from_u64(seed);
+    let mut table = Vec::new();
+    for op in OPERATORS {
+        match op.noise_kind() {
+            Some(NoiseKind::Simplex) | Some(NoiseKind::Value) => {
+                let range = calibrate(
+                    *op,
+                    DistanceFunction::Euclidean,
+                    ReturnType::Distance,
+                    samples,
+                    rng,
+                );
+                table.push((op.name().to_string(), range));
+            }
+            Some(NoiseKind::Cellular) => {
+                for distance in DISTANCE_FUNCTIONS {
+                    for return_type in &[ReturnType::Distance, ReturnType::CellValue] {
+                        let range = calibrate(*op, *distance, *return_type, samples, rng);
+                        let name =
+                            format!("{} {} {}", op.name(), distance.name(), return_type.name());
+                        table.push((name, range));
+                    }
+                }
+            }
+            Some(NoiseKind::Edges) => {
+                for distance in DISTANCE_FUNCTIONS {
+                    let range = calibrate(*op, *distance, ReturnType::Distance, samples, rng);
+                    table.push((format!("{} {}", op.name(), distance.name()), range));
+                }
+            }
+            None => (),
+        }
+    }
+    table
+}
+
+/// Raw output range of op with params, from calibration_table with 2000000
+/// samples a row and widened by about 3%. Run with --calibrate to measure again
+pub fn noise_range(op: Operator, params: &NoiseParams) -> NoiseRange {
+    let (min, max) = match (op, params.distance, params.return_type) {
+        (Operator::FBM, _, _) => (-0.042, 0.042),
+        (Operator::Ridge, _, _) => (0.977, 6.012),
+        (Operator::Turbulence, _, _) => (0.0, 0.042),
+        (Operator::FBM3D, _, _) => (-0.024, 0.024),
+        (Operator::Ridge3D, _, _) => (0.987, 6.006),
+        (Operator::Turbulence3D, _, _) => (0.0, 0.024),
+        (Operator::Value, _, _) => (-1.69, 1.66),
+        (Operator::Warp, _, _) => (-0.042, 0.042),
+        (Operator::CellEdges, DistanceFunction::Euclidean, _) => (0.0, 1.89),
+        (Operator::CellEdges, DistanceFunction::Manhattan, _) => (0.0, 1.68),
+        (Operator::CellEdges, DistanceFunction::Natural, _) => (0.0, 3.17),
+        (_, _, ReturnType::CellValue) => (-1.0, 1.0),
+        (Operator::Cell1, DistanceFunction::Euclidean, _)
+        | (Operator::Cell2, DistanceFunction::Euclidean, _) => (0.0, 1.34),
+        (Operator::Cell1, DistanceFunction::Manhattan, _)
+        | (Operator::Cell2, DistanceFunction::Manhattan, _) => (0.0, 1.66),
+        (Operator::Cell1, DistanceFunction::Natural, _)
+        | (Operator::Cell2, DistanceFunction::Natural, _) => (0.0, 2.98),
+        (_, DistanceFunction::Euclidean, _) => (0.0, 2.18),
+        (_, DistanceFunction::Manhattan, _) => (0.07, 2.31),
+        (_, DistanceFunction::Natural, _) => (0.1, 4.44),
+    };
+    NoiseRange { min, max }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ranges are measured from samples, so allow a sample a little outside
+    const TOLERANCE: f32 = 0.02;
+    const SAMPLES: usize = 20000;
+
+    #[test]
+    fn noise_stays_in_range() {
+        let rng = &mut StdRng::seed_from_u64(7);
+        for op in OPERATORS.iter().filter(|op| op.noise_kind().is_some()) {
+            for _ in 0..SAMPLES {
+                // random covers every setting but the return type, which
+                // mutation can still flip on cellular noise
+                let mut params = NoiseParams::random(*op, rng);
+                if op.noise_kind() == Some(NoiseKind::Cellular) && rng.gen() {
+                    params.return_type = match params.return_type {
+                        ReturnType::Distance => ReturnType::CellValue,
+                        ReturnType::CellValue => ReturnType::Distance,
+                    };
+                }
+                let args: Vec<<Scalar as Simd>::Vf32> = (0..op.arity())
+                    .map(|_| unsafe { Scalar::set1_ps(rng.gen_range(-1.0, 1.0)) })
+                    .collect();
+                let v = unsafe { scaled_noise_simd::<Scalar>(*op, &args[..], &params)[0] };
+                assert!(
+                    v.is_finite() && v >= -1.0 - TOLERANCE && v <= 1.0 + TOLERANCE,
+                    "{} gave {} with {:?}",
+                    op.name(),
+                    v,
+                    params
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn value_noise_far_from_the_origin() {
+        for &(x, y) in &[(3.0e9, -3.0e9), (-3.0e9, 3.0e9), (2.1e9, 0.5)] {
+            let v = unsafe { value_2d::<Scalar>(Scalar::set1_ps(x), Scalar::set1_ps(y), 3)[0] };
+            assert!(
+                v.is_finite() && v >= -1.0 && v <= 1.0,
+                "({}, {}) gave {}",
+                x,
+                y,
+                v
+            );
+        }
+    }
+}
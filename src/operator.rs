@@ -0,0 +1,616 @@
+use crate::noise::*;
+use crate::stack_machine::*;
+use simdeez::scalar::*;
+use simdeez::*;
+use simdnoise::*;
+
+// Every primitive the trees can use is declared once in the operators! list at
+// the bottom of this file. The macro expands that list into the Operator enum
+// plus the lookups the APT, parser, generator and stack machine consume, so a
+// plain primitive is one entry here and nothing else. Noise operators also need
+// a NoiseKind in noise_kind below and a range in noise.rs, operators that only
+// make sense in video go in TIME_OPERATORS, and transforms go in
+// TRANSFORM_OPERATORS with their coordinate change in transform.rs
+macro_rules! operators {
+    ($(
+        $variant:ident {
+            name: $name:expr,
+            arity: $arity:expr,
+            weight: $weight:expr,
+            scalar: |$sargs:ident| $scalar:expr,
+            simd: |$vargs:ident| $simd:expr $(,)?
+        }
+    ),* $(,)?) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        pub enum Operator {
+            $($variant),*
+        }
+
+        pub const OPERATORS: &[Operator] = &[$(Operator::$variant),*];
+
+        impl Operator {
+            /// The name used for this operator in lisp
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Operator::$variant => $name),*
+                }
+            }
+
+            /// How many children a node of this operator has
+            pub fn arity(self) -> usize {
+                match self {
+                    $(Operator::$variant => $arity),*
+                }
+            }
+
+            /// Relative chance of being picked when generating random trees
+            pub fn weight(self) -> usize {
+                match self {
+                    $(Operator::$variant => $weight),*
+                }
+            }
+
+            /// Evaluates the operator on plain floats, used for constant folding
+            pub fn scalar(self, args: &[f32]) -> f32 {
+                match self {
+                    $(Operator::$variant => {
+                        let $sargs = args;
+                        $scalar
+                    }),*
+                }
+            }
+
+            /// Evaluates the operator on simd lanes, args are in child order
+            #[inline(always)]
+            pub unsafe fn simd<S: Simd>(self, args: &[S::Vf32]) -> S::Vf32 {
+                match self {
+                    $(Operator::$variant => {
+                        let $vargs = args;
+                        $simd
+                    }),*
+                }
+            }
+
+            pub fn from_name(s: &str) -> Option<Operator> {
+                let lower = s.to_lowercase();
+                OPERATORS
+                    .iter()
+                    .find(|op| op.name().to_lowercase() == lower)
+                    .map(|op| *op)
+            }
+        }
+    };
+}
+
+// Runs the simd implementation with the scalar backend, for operators where
+// writing a second plain float version would just duplicate the noise libraries
+fn scalar_via_simd(op: Operator, args: &[f32]) -> f32 {
+    unsafe {
+        let lanes: Vec<<Scalar as Simd>::Vf32> =
+            args.iter().map(|arg| Scalar::set1_ps(*arg)).collect();
+        op.simd::<Scalar>(&lanes[..])[0]
+    }
+}
+
+// Noise outside of a node, with the settings noise had before nodes carried their own
+unsafe fn default_noise<S: Simd>(op: Operator, args: &[S::Vf32]) -> S::Vf32 {
+    noise_simd::<S>(op, args, &NoiseParams::new(op))
+}
+
+fn sign_preserving(v: f32, f: fn(f32) -> f32) -> f32 {
+    if v >= 0.0 {
+        f(v)
+    } else {
+        -f(-v)
+    }
+}
+
+fn wrap(v: f32) -> f32 {
+    if v >= -1.0 && v <= 1.0 {
+        v
+    } else {
+        let t = (v + 1.0) / 2.0;
+        -1.0 + 2.0 * (t - t.floor())
+    }
+}
+
+// Stripes at a frequency of 1 makes ten waves across the picture
+const STRIPE_FREQUENCY: f32 = 10.0 * std::f32::consts::PI;
+
+fn stripes(x: f32, y: f32, frequency: f32, angle: f32) -> f32 {
+    let angle = angle * std::f32::consts::PI;
+    ((x * angle.cos() + y * angle.sin()) * (frequency * STRIPE_FREQUENCY)).sin()
+}
+
+/// Operators built to move through time, the generator only picks them for video
+pub const TIME_OPERATORS: &[Operator] = &[
+    Operator::FBM3D,
+    Operator::Ridge3D,
+    Operator::Turbulence3D,
+    Operator::Cell3D1,
+    Operator::Cell3D2,
+];
+
+impl Operator {
+    pub fn uses_time(self) -> bool {
+        TIME_OPERATORS.contains(&self)
+    }
+
+    /// Noise operators carry NoiseParams in their nodes, see noise.rs
+    pub fn noise_kind(self) -> Option<NoiseKind> {
+        match self {
+            Operator::FBM
+            | Operator::Ridge
+            | Operator::Turbulence
+            | Operator::FBM3D
+            | Operator::Ridge3D
+            | Operator::Turbulence3D
+            | Operator::Warp => Some(NoiseKind::Simplex),
+            Operator::Value => Some(NoiseKind::Value),
+            Operator::Cell1 | Operator::Cell2 | Operator::Cell3D1 | Operator::Cell3D2 => {
+                Some(NoiseKind::Cellular)
+            }
+            Operator::CellEdges => Some(NoiseKind::Edges),
+            _ => None,
+        }
+    }
+}
+
+operators! {
+    Add {
+        name: "+",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0] + a[1],
+        simd: |a| a[0] + a[1],
+    },
+    Sub {
+        name: "-",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0] - a[1],
+        simd: |a| a[0] - a[1],
+    },
+    Mul {
+        name: "*",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0] * a[1],
+        simd: |a| a[0] * a[1],
+    },
+    Div {
+        name: "/",
+        arity: 2,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Div, a),
+        simd: |a| StackMachine::<S>::deal_with_nan(a[0] / a[1]),
+    },
+    // ( FBM x y xfreq yfreq lacunarity gain )
+    FBM {
+        name: "FBM",
+        arity: 6,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::FBM, a),
+        simd: |a| default_noise::<S>(Operator::FBM, a),
+    },
+    // ( Ridge x y xfreq yfreq lacunarity gain )
+    Ridge {
+        name: "Ridge",
+        arity: 6,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Ridge, a),
+        simd: |a| default_noise::<S>(Operator::Ridge, a),
+    },
+    // ( Turbulence x y xfreq yfreq lacunarity gain )
+    Turbulence {
+        name: "Turbulence",
+        arity: 6,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Turbulence, a),
+        simd: |a| default_noise::<S>(Operator::Turbulence, a),
+    },
+    // ( Cell1 x y xfreq yfreq jitter )
+    Cell1 {
+        name: "Cell1",
+        arity: 5,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Cell1, a),
+        simd: |a| default_noise::<S>(Operator::Cell1, a),
+    },
+    // ( Cell2 x y xfreq yfreq jitter )
+    Cell2 {
+        name: "Cell2",
+        arity: 5,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Cell2, a),
+        simd: |a| default_noise::<S>(Operator::Cell2, a),
+    },
+    // ( FBM3D x y t xfreq yfreq tfreq lacunarity gain )
+    FBM3D {
+        name: "FBM3D",
+        arity: 8,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::FBM3D, a),
+        simd: |a| default_noise::<S>(Operator::FBM3D, a),
+    },
+    // ( Ridge3D x y t xfreq yfreq tfreq lacunarity gain )
+    Ridge3D {
+        name: "Ridge3D",
+        arity: 8,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Ridge3D, a),
+        simd: |a| default_noise::<S>(Operator::Ridge3D, a),
+    },
+    // ( Turbulence3D x y t xfreq yfreq tfreq lacunarity gain )
+    Turbulence3D {
+        name: "Turbulence3D",
+        arity: 8,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Turbulence3D, a),
+        simd: |a| default_noise::<S>(Operator::Turbulence3D, a),
+    },
+    // ( Cell3D1 x y t xfreq yfreq tfreq jitter )
+    Cell3D1 {
+        name: "Cell3D1",
+        arity: 7,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Cell3D1, a),
+        simd: |a| default_noise::<S>(Operator::Cell3D1, a),
+    },
+    // ( Cell3D2 x y t xfreq yfreq tfreq jitter )
+    Cell3D2 {
+        name: "Cell3D2",
+        arity: 7,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Cell3D2, a),
+        simd: |a| default_noise::<S>(Operator::Cell3D2, a),
+    },
+    // ( CellEdges x y xfreq yfreq jitter ), the distance to the second nearest
+    // cell minus the nearest, dark along the edges between cells
+    CellEdges {
+        name: "CellEdges",
+        arity: 5,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::CellEdges, a),
+        simd: |a| default_noise::<S>(Operator::CellEdges, a),
+    },
+    // ( Value x y xfreq yfreq lacunarity gain )
+    Value {
+        name: "Value",
+        arity: 6,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Value, a),
+        simd: |a| default_noise::<S>(Operator::Value, a),
+    },
+    // ( Warp x y xfreq yfreq amount ), FBM looked up at coordinates pushed
+    // around by more FBM
+    Warp {
+        name: "Warp",
+        arity: 5,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Warp, a),
+        simd: |a| default_noise::<S>(Operator::Warp, a),
+    },
+    // ( Stripes x y frequency angle ), parallel waves across the plane
+    Stripes {
+        name: "Stripes",
+        arity: 4,
+        weight: 1,
+        scalar: |a| stripes(a[0], a[1], a[2], a[3]),
+        simd: |a| {
+            let angle = a[3] * S::set1_ps(std::f32::consts::PI);
+            let along = a[0] * S::fast_cos_ps(angle) + a[1] * S::fast_sin_ps(angle);
+            let frequency = a[2] * S::set1_ps(STRIPE_FREQUENCY);
+            S::fast_sin_ps(along * frequency)
+        },
+    },
+    // ( Rotate angle body ), see transform.rs. Transforms only change the
+    // coordinates their body sees, so on values alone they are the body
+    Rotate {
+        name: "Rotate",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[1],
+        simd: |a| a[1],
+    },
+    // ( Scale amount body ), see transform.rs
+    Scale {
+        name: "Scale",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[1],
+        simd: |a| a[1],
+    },
+    // ( Translate dx dy body ), see transform.rs
+    Translate {
+        name: "Translate",
+        arity: 3,
+        weight: 1,
+        scalar: |a| a[2],
+        simd: |a| a[2],
+    },
+    // ( MirrorX body ), see transform.rs
+    MirrorX {
+        name: "MirrorX",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0],
+        simd: |a| a[0],
+    },
+    // ( MirrorY body ), see transform.rs
+    MirrorY {
+        name: "MirrorY",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0],
+        simd: |a| a[0],
+    },
+    // ( Tile columns rows body ), see transform.rs
+    Tile {
+        name: "Tile",
+        arity: 3,
+        weight: 1,
+        scalar: |a| a[2],
+        simd: |a| a[2],
+    },
+    // ( Kaleidoscope segments body ), see transform.rs
+    Kaleidoscope {
+        name: "Kaleidoscope",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[1],
+        simd: |a| a[1],
+    },
+    Sqrt {
+        name: "Sqrt",
+        arity: 1,
+        weight: 1,
+        scalar: |a| sign_preserving(a[0], f32::sqrt),
+        simd: |a| {
+            let v = a[0];
+            let positive = S::sqrt_ps(v);
+            let negative = S::mul_ps(S::set1_ps(-1.0), S::sqrt_ps(S::abs_ps(v)));
+            let mask = S::cmpge_ps(v, S::setzero_ps());
+            S::blendv_ps(negative, positive, mask)
+        },
+    },
+    Sin {
+        name: "Sin",
+        arity: 1,
+        weight: 1,
+        scalar: |a| (a[0] * 3.14159).sin(),
+        simd: |a| S::fast_sin_ps(a[0] * S::set1_ps(3.14159)),
+    },
+    Atan {
+        name: "Atan",
+        arity: 1,
+        weight: 1,
+        scalar: |a| (a[0] * 4.0).atan() * 0.666666666,
+        simd: |a| S::fast_atan_ps(a[0] * S::set1_ps(4.0)) * S::set1_ps(0.666666666),
+    },
+    // ( Atan2 y x )
+    Atan2 {
+        name: "Atan2",
+        arity: 2,
+        weight: 1,
+        scalar: |a| (a[0] * 4.0).atan2(a[1]) * 0.318309,
+        simd: |a| S::fast_atan2_ps(a[0] * S::set1_ps(4.0), a[1]) * S::set1_ps(0.318309),
+    },
+    Tan {
+        name: "Tan",
+        arity: 1,
+        weight: 1,
+        scalar: |a| (a[0] * 1.57079632679).tan(),
+        simd: |a| S::fast_tan_ps(a[0] * S::set1_ps(1.57079632679)),
+    },
+    Log {
+        name: "Log",
+        arity: 1,
+        weight: 1,
+        scalar: |a| sign_preserving(a[0] * 4.0, f32::ln) * 0.367879,
+        simd: |a| {
+            let v = a[0] * S::set1_ps(4.0);
+            let positive = S::fast_ln_ps(v);
+            let negative = S::mul_ps(S::set1_ps(-1.0), S::fast_ln_ps(S::abs_ps(v)));
+            let mask = S::cmpge_ps(v, S::setzero_ps());
+            S::blendv_ps(negative, positive, mask) * S::set1_ps(0.367879)
+        },
+    },
+    Abs {
+        name: "Abs",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0].abs(),
+        simd: |a| S::abs_ps(a[0]),
+    },
+    Floor {
+        name: "Floor",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0].floor(),
+        simd: |a| S::fast_floor_ps(a[0]),
+    },
+    Ceil {
+        name: "Ceil",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0].ceil(),
+        simd: |a| S::fast_ceil_ps(a[0]),
+    },
+    Clamp {
+        name: "Clamp",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0].max(-1.0).min(1.0),
+        simd: |a| S::max_ps(S::min_ps(a[0], S::set1_ps(1.0)), S::set1_ps(-1.0)),
+    },
+    Wrap {
+        name: "Wrap",
+        arity: 1,
+        weight: 1,
+        scalar: |a| wrap(a[0]),
+        simd: |a| {
+            let mut v = a[0];
+            for i in 0..S::VF32_WIDTH {
+                v[i] = wrap(v[i]);
+            }
+            v
+        },
+    },
+    Square {
+        name: "Square",
+        arity: 1,
+        weight: 1,
+        scalar: |a| a[0] * a[0],
+        simd: |a| a[0] * a[0],
+    },
+    Max {
+        name: "Max",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0].max(a[1]),
+        simd: |a| S::max_ps(a[0], a[1]),
+    },
+    Min {
+        name: "Min",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0].min(a[1]),
+        simd: |a| S::min_ps(a[0], a[1]),
+    },
+    Mod {
+        name: "Mod",
+        arity: 2,
+        weight: 1,
+        scalar: |a| a[0] % a[1],
+        simd: |a| {
+            let mut r = S::setzero_ps();
+            for i in 0..S::VF32_WIDTH {
+                r[i] = a[0][i] % a[1][i];
+            }
+            r
+        },
+    },
+    // ( If cond a b ), a where cond is above 0, b elsewhere
+    If {
+        name: "If",
+        arity: 3,
+        weight: 1,
+        scalar: |a| if a[0] > 0.0 { a[1] } else { a[2] },
+        simd: |a| S::blendv_ps(a[2], a[1], S::cmpgt_ps(a[0], S::setzero_ps())),
+    },
+    // ( Lerp a b t ), a at a t of -1 through to b at 1
+    Lerp {
+        name: "Lerp",
+        arity: 3,
+        weight: 1,
+        scalar: |a| a[0] + (a[1] - a[0]) * ((a[2] + 1.0) * 0.5),
+        simd: |a| a[0] + (a[1] - a[0]) * ((a[2] + S::set1_ps(1.0)) * S::set1_ps(0.5)),
+    },
+    // ( Smoothstep edge0 edge1 x ), -1 before edge0 easing to 1 after edge1
+    Smoothstep {
+        name: "Smoothstep",
+        arity: 3,
+        weight: 1,
+        scalar: |a| scalar_via_simd(Operator::Smoothstep, a),
+        simd: |a| {
+            let t = StackMachine::<S>::deal_with_nan((a[2] - a[0]) / (a[1] - a[0]));
+            let t = S::max_ps(S::min_ps(t, S::set1_ps(1.0)), S::setzero_ps());
+            t * t * (S::set1_ps(6.0) - S::set1_ps(4.0) * t) - S::set1_ps(1.0)
+        },
+    },
+    // ( Step edge x ), 1 where x reaches edge, -1 elsewhere
+    Step {
+        name: "Step",
+        arity: 2,
+        weight: 1,
+        scalar: |a| if a[1] >= a[0] { 1.0 } else { -1.0 },
+        simd: |a| {
+            let mask = S::cmpge_ps(a[1], a[0]);
+            S::blendv_ps(S::set1_ps(-1.0), S::set1_ps(1.0), mask)
+        },
+    },
+    Mandlebrot {
+        name: "Mandlebrot",
+        arity: 2,
+        weight: 1,
+        //todo do
+        scalar: |a| a[1],
+        simd: |a| a[1],
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apt::*;
+    use rand::prelude::*;
+    use simdeez::avx2::*;
+    use simdeez::sse2::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    const TOLERANCE: f32 = 0.001;
+
+    // Runs node with x and y spread across the lanes and checks every lane
+    // against the scalar version evaluated at that lane's x and y
+    fn compare_lanes<S: Simd>(node: &APTNode, scalar: &dyn Fn(f32, f32) -> f32) {
+        let sm = StackMachine::<S>::build(node);
+        let pics = Arc::new(HashMap::new());
+        unsafe {
+            let mut stack = vec![S::setzero_ps(); sm.instructions.len()];
+            let (mut x, mut y) = (S::setzero_ps(), S::setzero_ps());
+            for i in 0..S::VF32_WIDTH {
+                x[i] = -0.9 + 0.23 * i as f32;
+                y[i] = 0.7 - 0.17 * i as f32;
+            }
+            let (t, t2) = (S::setzero_ps(), S::setzero_ps());
+            let v = sm.execute(&mut stack, pics, x, y, t, t2);
+            for i in 0..S::VF32_WIDTH {
+                let expected = scalar(x[i], y[i]);
+                assert!(
+                    (v[i] - expected).abs() < TOLERANCE,
+                    "{} lane {}: {} against {}",
+                    node.to_lisp(),
+                    i,
+                    v[i],
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn new_primitives_match_scalar() {
+        let rng = &mut StdRng::seed_from_u64(3);
+        for op in &[
+            Operator::CellEdges,
+            Operator::Value,
+            Operator::Warp,
+            Operator::Stripes,
+        ] {
+            for _ in 0..20 {
+                let args: Vec<f32> = (2..op.arity()).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+                let mut children = vec![APTNode::X, APTNode::Y];
+                children.extend(args.iter().map(|arg| APTNode::Constant(*arg)));
+                let params = NoiseParams::random(*op, rng);
+                let node = match op.noise_kind() {
+                    Some(_) => APTNode::Noise(*op, params, children),
+                    None => APTNode::Op(*op, children),
+                };
+                let scalar = |x: f32, y: f32| {
+                    let mut all = vec![x, y];
+                    all.extend(&args);
+                    match op.noise_kind() {
+                        Some(_) => noise_scalar(*op, &all, &params),
+                        None => stripes(all[0], all[1], all[2], all[3]),
+                    }
+                };
+                compare_lanes::<Sse2>(&node, &scalar);
+                if is_x86_feature_detected!("avx2") {
+                    compare_lanes::<Avx2>(&node, &scalar);
+                }
+            }
+        }
+    }
+}
@@ -6,6 +6,7 @@ pub enum Token<'a> {
     CloseParen(usize),
     Operation(&'a str, usize),
     Constant(&'a str, usize),
+    Text(&'a str, usize), // "quoted", without the quotes
 }
 
 // Function pointer definition must be wrapped in a struct to be recursive
@@ -55,8 +56,9 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // steps back over the last char read, nothing at the end of the input
     fn backup(&mut self) {
-        self.pos -= 1;
+        self.pos -= self.width;
     }
 
     fn ignore(&mut self) {
@@ -115,6 +117,26 @@ impl<'a> Lexer<'a> {
         return Some(StateFunction(Lexer::determine_token));
     }
 
+    fn lex_text(l: &mut Lexer) -> Option<StateFunction> {
+        l.ignore(); // drop the opening quote
+        loop {
+            match l.next() {
+                Some('"') => {
+                    l.backup();
+                    l.emit(Token::Text(&l.input[l.start..l.pos], l.current_line));
+                    l.next();
+                    l.ignore();
+                    return Some(StateFunction(Lexer::determine_token));
+                }
+                Some(_) => (),
+                None => {
+                    // unterminated, the parser will see the end of file
+                    return None;
+                }
+            }
+        }
+    }
+
     fn determine_token(l: &mut Lexer) -> Option<StateFunction> {
         loop {
             match l.next() {
@@ -125,6 +147,8 @@ impl<'a> Lexer<'a> {
                         l.emit(Token::OpenParen(l.current_line));
                     } else if c == ')' {
                         l.emit(Token::CloseParen(l.current_line));
+                    } else if c == '"' {
+                        return Some(StateFunction(Lexer::lex_text));
                     } else if Lexer::is_start_of_number(c) {
                         return Some(StateFunction(Lexer::lex_number));
                     } else {
@@ -1,6 +1,12 @@
 use crate::actual_picture::*;
+use crate::antialias::*;
 use crate::apt::*;
+use crate::breed::*;
+use crate::color::*;
+use crate::coordinates::*;
+use crate::generation::*;
 use crate::ggez_utility::*;
+use crate::hdr::*;
 use crate::parser::*;
 use crate::stack_machine::*;
 use ggez::graphics::Color;
@@ -9,6 +15,7 @@ use rand::*;
 use rayon::prelude::*;
 use simdeez::*;
 use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::mem::discriminant;
 use std::sync::mpsc::*;
 use std::sync::Arc;
@@ -17,18 +24,15 @@ use std::time::Instant;
 const GRADIENT_STOP_CHANCE: usize = 5; // 1 in 5
 const MAX_GRADIENT_COUNT: usize = 10;
 const MIN_GRADIENT_COUNT: usize = 2;
+const COORD_MUTATION_CHANCE: usize = 10; // 1 in 10
 pub const GRADIENT_SIZE: usize = 512;
 
 use CoordinateSystem::*;
-#[derive(Clone, PartialEq)]
-enum CoordinateSystem {
-    Polar,
-    Cartesian,
-}
 
 #[derive(Clone)]
 pub struct GradientData {
     colors: Vec<(Color, bool)>,
+    blend: Blend,
     index: APTNode,
     coord: CoordinateSystem,
 }
@@ -61,6 +65,24 @@ pub struct HSVData {
     coord: CoordinateSystem,
 }
 
+/// Lightness, green to red and blue to yellow in the OkLab space
+#[derive(Clone)]
+pub struct OkLabData {
+    l: APTNode,
+    a: APTNode,
+    b: APTNode,
+    coord: CoordinateSystem,
+}
+
+/// Lightness, chroma and hue in the OkLch space
+#[derive(Clone)]
+pub struct OkLchData {
+    l: APTNode,
+    c: APTNode,
+    h: APTNode,
+    coord: CoordinateSystem,
+}
+
 #[derive(Clone)]
 pub enum Pic {
     Grayscale(GrayscaleData),
@@ -68,6 +90,22 @@ pub enum Pic {
     HSV(HSVData),
     Gradient(GradientData),
     Mono(MonoData),
+    OkLab(OkLabData),
+    OkLch(OkLchData),
+}
+
+/// How many frames a video of d milliseconds at fps has
+pub fn frame_count(fps: u16, d: f32) -> usize {
+    (fps as f32 * (d / 1000.0)) as usize
+}
+
+/// When frame i of a video with frames frames happens
+pub fn frame_time(i: usize, frames: usize, looping: bool) -> Time {
+    if looping {
+        Time::looping(i as f32 / frames as f32)
+    } else {
+        Time::linear(-1.0 + 2.0 * i as f32 / frames as f32)
+    }
 }
 
 impl Pic {
@@ -77,13 +115,14 @@ impl Pic {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> Pic {
-        let tree = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
+        let tree = APTNode::generate(min, max, video, rng, pic_names, config);
         //let tree = APTNode::Cell2(vec![APTNode::X,APTNode::Y,APTNode::Constant(1.0)]);
         //let tree = APTNode::Picture("barn".to_string(),vec![APTNode::X,APTNode::Y]);
         Pic::Mono(MonoData {
             c: tree,
-            coord: Polar,
+            coord: CoordinateSystem::random(rng),
         })
     }
 
@@ -93,12 +132,13 @@ impl Pic {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> Pic {
-        let tree = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
+        let tree = APTNode::generate(min, max, video, rng, pic_names, config);
         //let tree = APTNode::Cell2(vec![APTNode::X,APTNode::Y,APTNode::Constant(1.0)]);
         Pic::Grayscale(GrayscaleData {
             c: tree,
-            coord: Polar,
+            coord: CoordinateSystem::random(rng),
         })
     }
 
@@ -108,6 +148,7 @@ impl Pic {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> Pic {
         //todo cleanup
         //color theory?
@@ -125,8 +166,9 @@ impl Pic {
 
         Pic::Gradient(GradientData {
             colors: colors,
-            index: APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names),
-            coord: Polar,
+            blend: Blend::random(rng),
+            index: APTNode::generate(min, max, video, rng, pic_names, config),
+            coord: CoordinateSystem::random(rng),
         })
     }
 
@@ -136,16 +178,17 @@ impl Pic {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> Pic {
-        let r = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
-        let g = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
-        let b = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
+        let r = APTNode::generate(min, max, video, rng, pic_names, config);
+        let g = APTNode::generate(min, max, video, rng, pic_names, config);
+        let b = APTNode::generate(min, max, video, rng, pic_names, config);
         //let noise = APTNode::FBM::<S>(vec![APTNode::X,APTNode::Y]);
         Pic::RGB(RGBData {
             r,
             g,
             b,
-            coord: Polar,
+            coord: CoordinateSystem::random(rng),
         })
     }
 
@@ -155,24 +198,153 @@ impl Pic {
         video: bool,
         rng: &mut StdRng,
         pic_names: &Vec<&String>,
+        config: &GenerationConfig,
     ) -> Pic {
-        let h = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
-        let s = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
-        let v = APTNode::generate_tree(rng.gen_range(min, max), video, rng, pic_names);
+        let h = APTNode::generate(min, max, video, rng, pic_names, config);
+        let s = APTNode::generate(min, max, video, rng, pic_names, config);
+        let v = APTNode::generate(min, max, video, rng, pic_names, config);
         Pic::HSV(HSVData {
             h,
             s,
             v,
-            coord: Polar,
+            coord: CoordinateSystem::random(rng),
+        })
+    }
+
+    pub fn new_oklab(
+        min: usize,
+        max: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> Pic {
+        let l = APTNode::generate(min, max, video, rng, pic_names, config);
+        let a = APTNode::generate(min, max, video, rng, pic_names, config);
+        let b = APTNode::generate(min, max, video, rng, pic_names, config);
+        Pic::OkLab(OkLabData {
+            l,
+            a,
+            b,
+            coord: CoordinateSystem::random(rng),
+        })
+    }
+
+    pub fn new_oklch(
+        min: usize,
+        max: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> Pic {
+        let l = APTNode::generate(min, max, video, rng, pic_names, config);
+        let c = APTNode::generate(min, max, video, rng, pic_names, config);
+        let h = APTNode::generate(min, max, video, rng, pic_names, config);
+        Pic::OkLch(OkLchData {
+            l,
+            c,
+            h,
+            coord: CoordinateSystem::random(rng),
         })
     }
 
+    /// One of the pic types above picked at random
+    pub fn new_random(
+        min: usize,
+        max: usize,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> Pic {
+        match rng.gen_range(0, 7) {
+            0 => Pic::new_mono(min, max, video, rng, pic_names, config),
+            1 => Pic::new_gradient(min, max, video, rng, pic_names, config),
+            2 => Pic::new_rgb(min, max, video, rng, pic_names, config),
+            3 => Pic::new_hsv(min, max, video, rng, pic_names, config),
+            4 => Pic::new_grayscale(min, max, video, rng, pic_names, config),
+            5 => Pic::new_oklab(min, max, video, rng, pic_names, config),
+            6 => Pic::new_oklch(min, max, video, rng, pic_names, config),
+            _ => panic!("invalid"),
+        }
+    }
+
+    pub fn trees(&self) -> Vec<&APTNode> {
+        match self {
+            Pic::Grayscale(data) => vec![&data.c],
+            Pic::Mono(data) => vec![&data.c],
+            Pic::Gradient(data) => vec![&data.index],
+            Pic::RGB(data) => vec![&data.r, &data.g, &data.b],
+            Pic::HSV(data) => vec![&data.h, &data.s, &data.v],
+            Pic::OkLab(data) => vec![&data.l, &data.a, &data.b],
+            Pic::OkLch(data) => vec![&data.l, &data.c, &data.h],
+        }
+    }
+
+    pub fn trees_mut(&mut self) -> Vec<&mut APTNode> {
+        match self {
+            Pic::Grayscale(data) => vec![&mut data.c],
+            Pic::Mono(data) => vec![&mut data.c],
+            Pic::Gradient(data) => vec![&mut data.index],
+            Pic::RGB(data) => vec![&mut data.r, &mut data.g, &mut data.b],
+            Pic::HSV(data) => vec![&mut data.h, &mut data.s, &mut data.v],
+            Pic::OkLab(data) => vec![&mut data.l, &mut data.a, &mut data.b],
+            Pic::OkLch(data) => vec![&mut data.l, &mut data.c, &mut data.h],
+        }
+    }
+
+    /// Total number of nodes across all the trees
+    pub fn size(&self) -> usize {
+        self.trees().iter().map(|tree| tree.size()).sum()
+    }
+
+    /// Keeps this pic's type and colors, crossing each of its trees with one of other's
+    pub fn crossover(
+        &self,
+        other: &Pic,
+        video: bool,
+        rng: &mut StdRng,
+        config: &GenerationConfig,
+    ) -> Pic {
+        let mut child = self.clone();
+        let donors = other.trees();
+        for tree in child.trees_mut() {
+            let donor = donors[rng.gen_range(0, donors.len())];
+            *tree = crossover_tree(tree, donor, video, rng, config);
+        }
+        child
+    }
+
+    /// Mutates one of the trees, or now and then the coordinate system
+    pub fn mutate(
+        &self,
+        video: bool,
+        rng: &mut StdRng,
+        pic_names: &Vec<&String>,
+        config: &GenerationConfig,
+    ) -> Pic {
+        let mut child = self.clone();
+        if rng.gen_range(0, COORD_MUTATION_CHANCE) == 0 {
+            let coord = child.coord().mutate(rng);
+            child.set_coord(coord);
+            return child;
+        }
+        let mut trees = child.trees_mut();
+        let i = rng.gen_range(0, trees.len());
+        *trees[i] = mutate_tree(trees[i], video, rng, pic_names, config);
+        child
+    }
+
     pub fn to_lisp(&self) -> String {
+        let coord = self.coord().to_lisp();
         match self {
-            Pic::Grayscale(data) => format!("( Grayscale\n {} )", data.c.to_lisp()),
-            Pic::Mono(data) => format!("( Mono\n {} )", data.c.to_lisp()),
+            Pic::Grayscale(data) => {
+                format!("( Grayscale\n {}\n ( Coord {} ) )", data.c.to_lisp(), coord)
+            }
+            Pic::Mono(data) => format!("( Mono\n {}\n ( Coord {} ) )", data.c.to_lisp(), coord),
             Pic::Gradient(data) => {
-                let mut colors = "( Colors ".to_string();
+                let mut colors = format!("( Colors ( Blend {} )", data.blend.name());
                 for (color, stop) in &data.colors {
                     if *stop {
                         colors += &format!(" ( StopColor {} {} {} )", color.r, color.g, color.b);
@@ -180,462 +352,553 @@ impl Pic {
                         colors += &format!(" ( Color {} {} {} )", color.r, color.g, color.b);
                     }
                 }
-                format!("( Gradient\n {} {} )", colors, data.index.to_lisp())
+                format!(
+                    "( Gradient\n {} )\n {}\n ( Coord {} ) )",
+                    colors,
+                    data.index.to_lisp(),
+                    coord
+                )
             }
             Pic::RGB(data) => format!(
-                "( RGB\n{} \n{}\n{} )",
+                "( RGB\n{} \n{}\n{}\n ( Coord {} ) )",
                 data.r.to_lisp(),
                 data.g.to_lisp(),
-                data.b.to_lisp()
+                data.b.to_lisp(),
+                coord
             ),
             Pic::HSV(data) => format!(
-                "( HSV\n{} \n{}\n{} )",
+                "( HSV\n{} \n{}\n{}\n ( Coord {} ) )",
                 data.h.to_lisp(),
                 data.s.to_lisp(),
-                data.v.to_lisp()
+                data.v.to_lisp(),
+                coord
+            ),
+            Pic::OkLab(data) => format!(
+                "( OkLab\n{} \n{}\n{}\n ( Coord {} ) )",
+                data.l.to_lisp(),
+                data.a.to_lisp(),
+                data.b.to_lisp(),
+                coord
+            ),
+            Pic::OkLch(data) => format!(
+                "( OkLch\n{} \n{}\n{}\n ( Coord {} ) )",
+                data.l.to_lisp(),
+                data.c.to_lisp(),
+                data.h.to_lisp(),
+                coord
             ),
         }
     }
 
+    fn coord(&self) -> &CoordinateSystem {
+        match self {
+            Pic::Grayscale(data) => &data.coord,
+            Pic::Mono(data) => &data.coord,
+            Pic::Gradient(data) => &data.coord,
+            Pic::RGB(data) => &data.coord,
+            Pic::HSV(data) => &data.coord,
+            Pic::OkLab(data) => &data.coord,
+            Pic::OkLch(data) => &data.coord,
+        }
+    }
+
+    fn set_coord(&mut self, coord: CoordinateSystem) {
+        match self {
+            Pic::Grayscale(data) => data.coord = coord,
+            Pic::Mono(data) => data.coord = coord,
+            Pic::Gradient(data) => data.coord = coord,
+            Pic::RGB(data) => data.coord = coord,
+            Pic::HSV(data) => data.coord = coord,
+            Pic::OkLab(data) => data.coord = coord,
+            Pic::OkLch(data) => data.coord = coord,
+        }
+    }
+
     pub fn get_video<S: Simd>(
         &self,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
         fps: u16,
         d: f32,
+        looping: bool,
     ) -> Vec<Vec<u8>> {
         let now = Instant::now();
-        let frames = (fps as f32 * (d / 1000.0)) as i32;
-        let frame_dt = 2.0 / frames as f32;
-
-        let mut t = -1.0;
         let mut result = Vec::new();
-        for _ in 0..frames {
-            let frame_buffer = self.get_rgba8::<S>(true, pics.clone(), w, h, t);
-            result.push(frame_buffer);
-            t += frame_dt;
-        }
+        let _ = self.stream_video::<S, _>(
+            pics,
+            w,
+            h,
+            viewport,
+            antialias,
+            fps,
+            d,
+            looping,
+            |_, frame| {
+                result.push(frame.to_vec());
+                Ok(())
+            },
+        );
         println!("img elapsed:{}", now.elapsed().as_millis());
         result
     }
 
+    /// Renders the video a frame at a time into one reused buffer and hands
+    /// the frames to sink in order, stopping at the first error sink returns
+    pub fn stream_video<S: Simd, F>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
+        fps: u16,
+        d: f32,
+        looping: bool,
+        mut sink: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(usize, &[u8]) -> Result<(), String>,
+    {
+        let frames = frame_count(fps, d);
+        let mut buffer = vec![0; w * h * 4];
+        for i in 0..frames {
+            let time = frame_time(i, frames, looping);
+            let pics = pics.clone();
+            self.render_rgba8::<S>(true, pics, w, h, viewport, antialias, time, &mut buffer);
+            sink(i, &buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Like stream_video but renders several frames at once, so sink is called
+    /// from many threads with the frames in any order. Each thread keeps one buffer
+    pub fn stream_video_unordered<S: Simd, F>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
+        fps: u16,
+        d: f32,
+        looping: bool,
+        sink: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(usize, &[u8]) -> Result<(), String> + Sync,
+    {
+        let frames = frame_count(fps, d);
+        (0..frames)
+            .into_par_iter()
+            .map_init(
+                || vec![0; w * h * 4],
+                |buffer, i| {
+                    let time = frame_time(i, frames, looping);
+                    let pics = pics.clone();
+                    self.render_rgba8::<S>(true, pics, w, h, viewport, antialias, time, buffer);
+                    sink(i, buffer)
+                },
+            )
+            .collect()
+    }
+
     pub fn get_rgba8<S: Simd>(
         &self,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
+        viewport: &Viewport,
+        antialias: Antialias,
+        time: Time,
     ) -> Vec<u8> {
+        let mut result = vec![0; w * h * 4];
+        self.render_rgba8::<S>(threaded, pics, w, h, viewport, antialias, time, &mut result);
+        result
+    }
+
+    /// Like get_rgba8 but draws into result, which has to hold w * h * 4 bytes
+    pub fn render_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
+        time: Time,
+        result: &mut [u8],
+    ) {
+        self.render::<S, u8>(threaded, pics, w, h, viewport, antialias, time, result)
+    }
+
+    /// Rgba floats where 0 is black and 1 is full brightness, left unclamped so
+    /// the overshoot of trees that leave -1 to 1 can be kept or graded. Trees that
+    /// fold their values round, like Mono and HSV, never overshoot
+    pub fn get_rgbaf32<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
+        time: Time,
+    ) -> Vec<f32> {
+        let mut result = vec![0.0; w * h * 4];
+        self.render::<S, f32>(threaded, pics, w, h, viewport, antialias, time, &mut result);
+        result
+    }
+
+    fn render<S: Simd, C: Channel>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        antialias: Antialias,
+        time: Time,
+        result: &mut [C],
+    ) {
+        let now = Instant::now();
+        match antialias {
+            Antialias::Off => {
+                self.render_single::<S, C>(threaded, pics, w, h, viewport, time, result)
+            }
+            Antialias::Grid(n) => {
+                render_grid::<S, C>(self, threaded, pics, w, h, viewport, n, time, result)
+            }
+            Antialias::Adaptive(n, threshold) => render_adaptive::<S, C>(
+                self, threaded, pics, w, h, viewport, n, threshold, time, result,
+            ),
+        }
+        println!("img elapsed:{}", now.elapsed().as_millis());
+    }
+
+    /// Renders with one sample in the middle of each pixel
+    pub fn render_single<S: Simd, C: Channel>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
         match self {
-            Pic::Mono(data) => Pic::get_rgba8_mono::<S>(data, threaded, pics, w, h, t),
-            Pic::Grayscale(data) => Pic::get_rgba8_grayscale::<S>(data, threaded, pics, w, h, t),
-            Pic::Gradient(data) => Pic::get_rgba8_gradient::<S>(data, threaded, pics, w, h, t),
-            Pic::RGB(data) => Pic::get_rgba8_rgb::<S>(data, threaded, pics, w, h, t),
-            Pic::HSV(data) => Pic::get_rgba8_hsv::<S>(data, threaded, pics, w, h, t),
+            Pic::Mono(data) => {
+                Pic::render_mono::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::Grayscale(data) => {
+                Pic::render_grayscale::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::Gradient(data) => {
+                Pic::render_gradient::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::RGB(data) => {
+                Pic::render_rgb::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::HSV(data) => {
+                Pic::render_hsv::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::OkLab(data) => {
+                Pic::render_oklab::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
+            Pic::OkLch(data) => {
+                Pic::render_oklch::<S, C>(data, threaded, pics, w, h, viewport, time, result)
+            }
         }
     }
 
-    fn get_rgba8_gradient<S: Simd>(
+    fn render_gradient<S: Simd, C: Channel>(
         data: &GradientData,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
-    ) -> Vec<u8> {
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
         unsafe {
-            let now = Instant::now();
-            let ts = S::set1_ps(t);
-            let vec_len = w * h * 4;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
             let sm = StackMachine::<S>::build(&data.index);
-            let mut min = 999999.0;
-            let mut max = -99999.0;
-
-            let color_count = data.colors.iter().filter(|(_, stop)| !stop).count();
-            let mut gradient = Vec::<Color>::new(); //todo actually compute this
-            let step = (GRADIENT_SIZE as f32 / color_count as f32) / GRADIENT_SIZE as f32;
-            let mut positions = Vec::<f32>::new();
-            positions.push(0.0);
-            let mut pos = step;
-            for i in 1..data.colors.len() - 1 {
-                let (_, stop) = data.colors[i];
-                if stop {
-                    positions.push(*positions.last().unwrap());
-                } else {
-                    positions.push(pos);
-                    pos += step;
-                }
-            }
-            positions.push(1.0);
-
-            for i in 0..GRADIENT_SIZE {
-                let pct = i as f32 / GRADIENT_SIZE as f32;
-                let color2pos = positions.iter().position(|n| *n >= pct).unwrap();
-                if color2pos == 0 {
-                    gradient.push(data.colors[0].0);
-                } else {
-                    let color1 = data.colors[color2pos - 1].0;
-                    let color2 = data.colors[color2pos].0;
-                    let pct2 = positions[color2pos];
-                    let pct1 = positions[color2pos - 1];
-                    let range = pct2 - pct1;
-                    let pct = (pct - pct1) / range;
-                    gradient.push(lerp_color(color1, color2, pct));
-                }
-            }
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
-
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
-                let mut x = S::setzero_ps();
-                for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
-                }
-                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if data.coord == Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts)
-                    } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts)
-                    };
-                    let scaled_v = (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
-                    let index = S::cvtps_epi32(scaled_v * S::set1_ps(GRADIENT_SIZE as f32));
-
-                    for j in 0..S::VF32_WIDTH {
-                        let c = gradient[index[j] as usize % GRADIENT_SIZE];
-                        chunk[i + j * 4] = (c.r * 255.0) as u8;
-                        chunk[i + 1 + j * 4] = (c.g * 255.0) as u8;
-                        chunk[i + 2 + j * 4] = (c.b * 255.0) as u8;
-                        chunk[i + 3 + j * 4] = 255 as u8;
-                    }
-                    x = x + x_step;
+            let gradient = gradient_table(&data.colors, data.blend);
+            let len = sm.instructions.len();
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let v = sm.execute(stack, pics.clone(), x, y, ts, t2s);
+                let scaled_v = (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                let index = S::cvtps_epi32(scaled_v * S::set1_ps(GRADIENT_SIZE as f32));
+                let (mut rs, mut gs, mut bs) = (S::setzero_ps(), S::setzero_ps(), S::setzero_ps());
+                for j in 0..S::VF32_WIDTH {
+                    let c = gradient[index[j] as usize % GRADIENT_SIZE];
+                    rs[j] = c.r;
+                    gs[j] = c.g;
+                    bs[j] = c.b;
                 }
+                (rs, gs, bs)
             };
-
-            if threaded {
-                result.par_chunks_mut(4 * w).enumerate().for_each(process);
-            } else {
-                result.chunks_exact_mut(4 * w).enumerate().for_each(process);
-            }
-
-            // println!("min:{} max:{} range:{}",min,max,max-min);
-            println!("img elapsed:{}", now.elapsed().as_millis());
-            result
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
         }
     }
 
-    fn get_rgba8_grayscale<S: Simd>(
+    fn render_grayscale<S: Simd, C: Channel>(
         data: &GrayscaleData,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
-    ) -> Vec<u8> {
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
         unsafe {
-            let now = Instant::now();
-            let ts = S::set1_ps(t);
-            let vec_len = w * h * 4;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
             let sm = StackMachine::<S>::build(&data.c);
-            let mut min = 999999.0;
-            let mut max = -99999.0;
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
-
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
-                let mut x = S::setzero_ps();
-                for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
-                }
-                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if data.coord == Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts)
-                    } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts)
-                    };
-
-                    // if v[0] > max { max = v[0]; }
-                    // if v[0] < min { min = v[0]; }
-
-                    let cs = (v + S::set1_ps(1.0)) * S::set1_ps(127.5);
-
-                    for j in 0..S::VF32_WIDTH {
-                        let c = (cs[j] as i32 % 256) as u8;
-                        chunk[i + j * 4] = c;
-                        chunk[i + 1 + j * 4] = c;
-                        chunk[i + 2 + j * 4] = c;
-                        chunk[i + 3 + j * 4] = 255 as u8;
-                    }
-                    x = x + x_step;
-                }
+            let len = sm.instructions.len();
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let v = sm.execute(stack, pics.clone(), x, y, ts, t2s);
+                let c = (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                (c, c, c)
             };
-
-            if threaded {
-                result.par_chunks_mut(4 * w).enumerate().for_each(process);
-            } else {
-                result.chunks_exact_mut(4 * w).enumerate().for_each(process);
-            }
-            // println!("min:{} max:{} range:{}",min,max,max-min);
-            println!("img elapsed:{}", now.elapsed().as_millis());
-            result
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
         }
     }
 
-    fn get_rgba8_mono<S: Simd>(
+    fn render_mono<S: Simd, C: Channel>(
         data: &MonoData,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
-    ) -> Vec<u8> {
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
         unsafe {
-            let now = Instant::now();
-            let ts = S::set1_ps(t);
-            let vec_len = w * h * 4;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
             let sm = StackMachine::<S>::build(&data.c);
-            let mut min = 999999.0;
-            let mut max = -99999.0;
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
-
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
-                let mut x = S::setzero_ps();
-                for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
-                }
-                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if data.coord == Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts)
-                    } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts)
-                    };
-
-                    for j in 0..S::VF32_WIDTH {
-                        let c = if v[j] >= 0.0 { 255 } else { 0 };
-                        chunk[i + j * 4] = c;
-                        chunk[i + 1 + j * 4] = c;
-                        chunk[i + 2 + j * 4] = c;
-                        chunk[i + 3 + j * 4] = 255 as u8;
-                    }
-                    x = x + x_step;
-                }
+            let len = sm.instructions.len();
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let v = sm.execute(stack, pics.clone(), x, y, ts, t2s);
+                let zero = S::setzero_ps();
+                let c = S::blendv_ps(zero, S::set1_ps(1.0), S::cmpge_ps(v, zero));
+                (c, c, c)
             };
-
-            if threaded {
-                result.par_chunks_mut(4 * w).enumerate().for_each(process);
-            } else {
-                result.chunks_exact_mut(4 * w).enumerate().for_each(process);
-            }
-            // println!("min:{} max:{} range:{}",min,max,max-min);
-            println!("img elapsed:{}", now.elapsed().as_millis());
-            result
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
         }
     }
 
-    fn get_rgba8_rgb<S: Simd>(
+    fn render_rgb<S: Simd, C: Channel>(
         data: &RGBData,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
-    ) -> Vec<u8> {
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
         unsafe {
-            let now = Instant::now();
-            let ts = S::set1_ps(t);
-
-            let vec_len = w * h * 4;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
-
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
             let r_sm = StackMachine::<S>::build(&data.r);
             let g_sm = StackMachine::<S>::build(&data.g);
             let b_sm = StackMachine::<S>::build(&data.b);
-            let max_len = *[
-                r_sm.instructions.len(),
-                g_sm.instructions.len(),
-                b_sm.instructions.len(),
-            ]
-            .iter()
-            .max()
-            .unwrap();
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(max_len);
-                stack.set_len(max_len);
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
-                let mut x = S::setzero_ps();
-                for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
-                }
-                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let (rs, gs, bs) = if data.coord == Cartesian {
-                        let rs = (r_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        let gs = (g_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        let bs = (b_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        (rs, gs, bs)
-                    } else {
-                        let (x, y) = cartesian_to_polar::<S>(x, y);
-                        let rs = (r_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        let gs = (g_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        let bs = (b_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(128.0);
-                        (rs, gs, bs)
-                    };
-
-                    for j in 0..S::VF32_WIDTH {
-                        let r = (rs[j] as i32 % 255) as u8;
-                        let g = (gs[j] as i32 % 255) as u8;
-                        let b = (bs[j] as i32 % 255) as u8;
-                        chunk[i + j * 4] = r;
-                        chunk[i + 1 + j * 4] = g;
-                        chunk[i + 2 + j * 4] = b;
-                        chunk[i + 3 + j * 4] = 255 as u8;
-                    }
-                    x = x + x_step;
-                }
+            let len = stack_len(&[&r_sm, &g_sm, &b_sm]);
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let unit = |v: S::Vf32| (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                (
+                    unit(r_sm.execute(stack, pics.clone(), x, y, ts, t2s)),
+                    unit(g_sm.execute(stack, pics.clone(), x, y, ts, t2s)),
+                    unit(b_sm.execute(stack, pics.clone(), x, y, ts, t2s)),
+                )
             };
-            if threaded {
-                result.par_chunks_mut(4 * w).enumerate().for_each(process);
-            } else {
-                result.chunks_exact_mut(4 * w).enumerate().for_each(process);
-            }
-
-            println!("img elapsed:{}", now.elapsed().as_millis());
-            result
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
         }
     }
 
-    fn get_rgba8_hsv<S: Simd>(
+    fn render_hsv<S: Simd, C: Channel>(
         data: &HSVData,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: usize,
         h: usize,
-        t: f32,
-    ) -> Vec<u8> {
-        unsafe {            
-            let ts = S::set1_ps(t);
-            let vec_len = w * h * 4;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
-
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
+        unsafe {
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
             let h_sm = StackMachine::<S>::build(&data.h);
             let s_sm = StackMachine::<S>::build(&data.s);
             let v_sm = StackMachine::<S>::build(&data.v);
-            let max_len = *[
-                h_sm.instructions.len(),
-                s_sm.instructions.len(),
-                v_sm.instructions.len(),
-            ]
-            .iter()
-            .max()
-            .unwrap();
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(max_len);
-                stack.set_len(max_len);
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
-                let mut x = S::setzero_ps();
-                for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
-                }
-                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let (hs, ss, vs) = if data.coord == Cartesian {
-                        let hs = (h_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let ss = (s_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let vs = (v_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        (hs, ss, vs)
-                    } else {
-                        let (x, y) = cartesian_to_polar::<S>(x, y);
-                        let hs = (h_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let ss = (s_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let vs = (v_sm.execute(&mut stack, pics.clone(), x, y, ts)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        (hs, ss, vs)
-                    };
-
-                    let (mut rs, mut gs, mut bs) =
-                        hsv_to_rgb::<S>(wrap_0_1::<S>(hs), wrap_0_1::<S>(ss), wrap_0_1::<S>(vs));
-                    rs = rs * S::set1_ps(255.0);
-                    gs = gs * S::set1_ps(255.0);
-                    bs = bs * S::set1_ps(255.0);
-                    for j in 0..S::VF32_WIDTH {
-                        let r = (rs[j] as i32 % 255) as u8;
-                        let g = (gs[j] as i32 % 255) as u8;
-                        let b = (bs[j] as i32 % 255) as u8;
-                        chunk[i + j * 4] = r;
-                        chunk[i + 1 + j * 4] = g;
-                        chunk[i + 2 + j * 4] = b;
-                        chunk[i + 3 + j * 4] = 255 as u8;
-                    }
-                    x = x + x_step;
-                }
+            let len = stack_len(&[&h_sm, &s_sm, &v_sm]);
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let unit = |v: S::Vf32| wrap_0_1::<S>((v + S::set1_ps(1.0)) * S::set1_ps(0.5));
+                let hs = unit(h_sm.execute(stack, pics.clone(), x, y, ts, t2s));
+                let ss = unit(s_sm.execute(stack, pics.clone(), x, y, ts, t2s));
+                let vs = unit(v_sm.execute(stack, pics.clone(), x, y, ts, t2s));
+                hsv_to_rgb::<S>(hs, ss, vs)
             };
-            if threaded {
-                result.par_chunks_mut(4 * w).enumerate().for_each(process);
-            } else {
-                result.chunks_exact_mut(4 * w).enumerate().for_each(process);
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
+        }
+    }
+
+    fn render_oklab<S: Simd, C: Channel>(
+        data: &OkLabData,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
+        unsafe {
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
+            let l_sm = StackMachine::<S>::build(&data.l);
+            let a_sm = StackMachine::<S>::build(&data.a);
+            let b_sm = StackMachine::<S>::build(&data.b);
+            let len = stack_len(&[&l_sm, &a_sm, &b_sm]);
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let chroma = S::set1_ps(MAX_CHROMA);
+                let l = l_sm.execute(stack, pics.clone(), x, y, ts, t2s);
+                let a = a_sm.execute(stack, pics.clone(), x, y, ts, t2s) * chroma;
+                let b = b_sm.execute(stack, pics.clone(), x, y, ts, t2s) * chroma;
+                oklab_to_rgb::<S>((l + S::set1_ps(1.0)) * S::set1_ps(0.5), a, b)
+            };
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
+        }
+    }
+
+    fn render_oklch<S: Simd, C: Channel>(
+        data: &OkLchData,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: usize,
+        h: usize,
+        viewport: &Viewport,
+        time: Time,
+        result: &mut [C],
+    ) {
+        unsafe {
+            let ts = S::set1_ps(time.t);
+            let t2s = S::set1_ps(time.t2);
+            let l_sm = StackMachine::<S>::build(&data.l);
+            let c_sm = StackMachine::<S>::build(&data.c);
+            let h_sm = StackMachine::<S>::build(&data.h);
+            let len = stack_len(&[&l_sm, &c_sm, &h_sm]);
+            let shade = |stack: &mut Vec<S::Vf32>, x, y| {
+                let unit = |v: S::Vf32| (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                let l = unit(l_sm.execute(stack, pics.clone(), x, y, ts, t2s));
+                let c = unit(c_sm.execute(stack, pics.clone(), x, y, ts, t2s));
+                // -1 to 1 is once round the hue circle
+                let h = h_sm.execute(stack, pics.clone(), x, y, ts, t2s) * S::set1_ps(PI);
+                oklch_to_rgb::<S>(l, c * S::set1_ps(MAX_CHROMA), h)
+            };
+            render_pixels::<S, C, _>(threaded, w, h, viewport, data.coord, len, result, shade);
+        }
+    }
+}
+
+/// Runs shade on every pixel of a w by h image and writes the red, green and
+/// blue from 0 to 1 it returns into result. shade gets a stack with room for
+/// stack_len values and the coordinates of a pixel after coord has mapped them
+fn render_pixels<S: Simd, C: Channel, F>(
+    threaded: bool,
+    w: usize,
+    h: usize,
+    viewport: &Viewport,
+    coord: CoordinateSystem,
+    stack_len: usize,
+    result: &mut [C],
+    shade: F,
+) where
+    F: Fn(&mut Vec<S::Vf32>, S::Vf32, S::Vf32) -> (S::Vf32, S::Vf32, S::Vf32) + Sync,
+{
+    let process = |(y_pixel, chunk): (usize, &mut [C])| unsafe {
+        let mut stack = Vec::with_capacity(stack_len);
+        stack.set_len(stack_len);
+        let (mut x, mut y, x_step, y_step) = viewport.row::<S>(y_pixel, w, h);
+        for x_pixel in (0..w).step_by(S::VF32_WIDTH) {
+            let (cx, cy) = coord.map::<S>(x, y);
+            let (rs, gs, bs) = shade(&mut stack, cx, cy);
+            // when w isn't a multiple of the simd width the last lanes fall off
+            // the end of the row, they are shaded but never written
+            for j in 0..S::VF32_WIDTH.min(w - x_pixel) {
+                let i = (x_pixel + j) * 4;
+                chunk[i] = C::from_unit(rs[j]);
+                chunk[i + 1] = C::from_unit(gs[j]);
+                chunk[i + 2] = C::from_unit(bs[j]);
+                chunk[i + 3] = C::from_unit(1.0);
             }
+            x = x + x_step;
+            y = y + y_step;
+        }
+    };
+    if threaded {
+        result.par_chunks_mut(4 * w).enumerate().for_each(process);
+    } else {
+        result.chunks_exact_mut(4 * w).enumerate().for_each(process);
+    }
+}
+
+// The stack one buffer needs to run all of the stack machines
+fn stack_len<S: Simd>(machines: &[&StackMachine<S>]) -> usize {
+    machines
+        .iter()
+        .map(|sm| sm.instructions.len())
+        .max()
+        .unwrap()
+}
 
-            //   println!("img elapsed:{}", now.elapsed().as_millis());
-            result
+/// GRADIENT_SIZE colours blended between the gradient's colours. A stop sits where
+/// the colour before it does, so the gradient jumps there instead of blending
+fn gradient_table(colors: &[(Color, bool)], blend: Blend) -> Vec<Color> {
+    let color_count = colors.iter().filter(|(_, stop)| !stop).count();
+    let mut gradient = Vec::<Color>::new();
+    let step = (GRADIENT_SIZE as f32 / color_count as f32) / GRADIENT_SIZE as f32;
+    let mut positions = Vec::<f32>::new();
+    positions.push(0.0);
+    let mut pos = step;
+    for i in 1..colors.len() - 1 {
+        let (_, stop) = colors[i];
+        if stop {
+            positions.push(*positions.last().unwrap());
+        } else {
+            positions.push(pos);
+            pos += step;
+        }
+    }
+    positions.push(1.0);
+
+    for i in 0..GRADIENT_SIZE {
+        let pct = i as f32 / GRADIENT_SIZE as f32;
+        let color2pos = positions.iter().position(|n| *n >= pct).unwrap();
+        if color2pos == 0 {
+            gradient.push(colors[0].0);
+        } else {
+            let color1 = colors[color2pos - 1].0;
+            let color2 = colors[color2pos].0;
+            let pct2 = positions[color2pos];
+            let pct1 = positions[color2pos - 1];
+            let range = pct2 - pct1;
+            let pct = (pct - pct1) / range;
+            gradient.push(blend.mix(color1, color2, pct));
         }
     }
+    gradient
 }
 
 pub fn lisp_to_pic(code: String) -> Result<Pic, String> {
@@ -653,7 +916,7 @@ pub fn lisp_to_pic(code: String) -> Result<Pic, String> {
 pub fn extract_line_number(token: &Token) -> usize {
     match token {
         Token::OpenParen(ln) | Token::CloseParen(ln) => *ln,
-        Token::Constant(_, ln) | Token::Operation(_, ln) => *ln,
+        Token::Constant(_, ln) | Token::Operation(_, ln) | Token::Text(_, ln) => *ln,
     }
 }
 
@@ -678,7 +941,7 @@ pub fn expect_close_paren(receiver: &Receiver<Token>) -> Result<(), String> {
         Token::CloseParen(_) => Ok(()),
         _ => {
             return Err(format!(
-                "Expected '(' on line {}",
+                "Expected ')' on line {}",
                 extract_line_number(&close_paren)
             ))
         }
@@ -751,43 +1014,109 @@ pub fn expect_constant(receiver: &Receiver<Token>) -> Result<f32, String> {
     }
 }
 
+#[must_use]
+pub fn expect_integer(receiver: &Receiver<Token>) -> Result<u64, String> {
+    let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    match token {
+        Token::Constant(vstr, line_number) => vstr
+            .parse::<u64>()
+            .map_err(|_| format!("Unable to parse integer {} on line {}", vstr, line_number)),
+        _ => Err(format!(
+            "Expected integer on line {}, found {:?}",
+            extract_line_number(&token),
+            token
+        )),
+    }
+}
+
+/// Reads integers up to and including the closing paren
+#[must_use]
+pub fn expect_integers(receiver: &Receiver<Token>) -> Result<Vec<u64>, String> {
+    let mut integers = Vec::new();
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => return Ok(integers),
+            Token::Constant(vstr, line_number) => integers.push(vstr.parse::<u64>().map_err(
+                |_| format!("Unable to parse integer {} on line {}", vstr, line_number),
+            )?),
+            _ => {
+                return Err(format!(
+                    "Expected integer on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+    }
+}
+
 pub fn parse_pic(receiver: &Receiver<Token>) -> Result<Pic, String> {
     expect_open_paren(receiver)?;
     let pic_type = receiver.recv().map_err(|_| "Unexpected end of file")?;
-    match pic_type {
+    let mut pic = match pic_type {
         Token::Operation(s, line_number) => match &s.to_lowercase()[..] {
-            "Grayscale" => Ok(Pic::Grayscale(GrayscaleData {
+            "grayscale" => Pic::Grayscale(GrayscaleData {
+                c: APTNode::parse_apt_node(receiver)?,
+                coord: Cartesian,
+            }),
+            "mono" => Pic::Mono(MonoData {
                 c: APTNode::parse_apt_node(receiver)?,
                 coord: Cartesian,
-            })),
-            "rgb" => Ok(Pic::RGB(RGBData {
+            }),
+            "rgb" => Pic::RGB(RGBData {
                 r: APTNode::parse_apt_node(receiver)?,
                 g: APTNode::parse_apt_node(receiver)?,
                 b: APTNode::parse_apt_node(receiver)?,
                 coord: Cartesian,
-            })),
-            "hsv" => Ok(Pic::HSV(HSVData {
+            }),
+            "hsv" => Pic::HSV(HSVData {
                 h: APTNode::parse_apt_node(receiver)?,
                 s: APTNode::parse_apt_node(receiver)?,
                 v: APTNode::parse_apt_node(receiver)?,
                 coord: Cartesian,
-            })),
+            }),
+            "oklab" => Pic::OkLab(OkLabData {
+                l: APTNode::parse_apt_node(receiver)?,
+                a: APTNode::parse_apt_node(receiver)?,
+                b: APTNode::parse_apt_node(receiver)?,
+                coord: Cartesian,
+            }),
+            "oklch" => Pic::OkLch(OkLchData {
+                l: APTNode::parse_apt_node(receiver)?,
+                c: APTNode::parse_apt_node(receiver)?,
+                h: APTNode::parse_apt_node(receiver)?,
+                coord: Cartesian,
+            }),
             "gradient" => {
                 let mut colors = Vec::new();
+                // gradients from before blending had a choice blended in sRGB
+                let mut blend = Blend::Srgb;
                 expect_open_paren(receiver)?;
                 expect_operation("colors", receiver)?;
 
                 loop {
                     let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
-                    if discriminant(&token) == discriminant(&Token::CloseParen(0)) {
-                        break;
+                    match token {
+                        Token::CloseParen(_) => break,
+                        Token::OpenParen(_) => (),
+                        _ => {
+                            return Err(format!(
+                                "Expected '(' on line {}",
+                                extract_line_number(&token)
+                            ))
+                        }
+                    }
+                    let color_type =
+                        expect_operations(vec!["color", "stopcolor", "blend"], receiver)?;
+                    if color_type.to_lowercase() == "blend" {
+                        blend = expect_blend(receiver)?;
+                        expect_close_paren(receiver)?;
+                        continue;
                     }
-                    expect_open_paren(receiver)?;
-                    let color_type = expect_operations(vec!["color", "stopcolor"], receiver)?;
                     let r = expect_constant(receiver)?;
                     let g = expect_constant(receiver)?;
                     let b = expect_constant(receiver)?;
-                    if color_type == "color" {
+                    if color_type.to_lowercase() == "color" {
                         colors.push((Color::new(r, g, b, 1.0), false));
                     } else {
                         colors.push((Color::new(r, g, b, 1.0), true));
@@ -795,15 +1124,47 @@ pub fn parse_pic(receiver: &Receiver<Token>) -> Result<Pic, String> {
                     expect_close_paren(receiver)?;
                 }
 
-                Ok(Pic::Gradient(GradientData {
+                Pic::Gradient(GradientData {
                     colors: colors,
+                    blend,
                     index: APTNode::parse_apt_node(receiver)?,
                     coord: Cartesian,
-                }))
+                })
             }
-            _ => Err(format!("Unknown pic type {} at line {}", s, line_number)),
+            _ => return Err(format!("Unknown pic type {} at line {}", s, line_number)),
+        },
+        _ => return Err(format!("Invalid picture type")), //todo line number etc
+    };
+    // optional ( Coord Polar ) after the trees, older pics leave it out
+    let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    match token {
+        Token::CloseParen(_) => return Ok(pic),
+        Token::OpenParen(_) => (),
+        _ => {
+            return Err(format!(
+                "Expected ')' on line {}",
+                extract_line_number(&token)
+            ))
+        }
+    }
+    expect_operation("coord", receiver)?;
+    pic.set_coord(parse_coordinate_system(receiver)?);
+    expect_close_paren(receiver)?;
+    expect_close_paren(receiver)?;
+    Ok(pic)
+}
+
+fn expect_blend(receiver: &Receiver<Token>) -> Result<Blend, String> {
+    let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    match token {
+        Token::Operation(s, line_number) => match Blend::from_name(s) {
+            Some(blend) => Ok(blend),
+            None => Err(format!("Unknown blend {} on line {}", s, line_number)),
         },
-        _ => Err(format!("Invalid picture type")), //todo line number etc
+        _ => Err(format!(
+            "Expected a blend on line {}",
+            extract_line_number(&token)
+        )),
     }
 }
 
@@ -818,24 +1179,6 @@ fn wrap_0_1<S: Simd>(v: S::Vf32) -> S::Vf32 {
     }
 }
 
-#[inline(always)]
-fn cartesian_to_polar<S: Simd>(x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32) {
-    unsafe {
-        let zero = S::set1_ps(0.0);
-        let pi = S::set1_ps(3.14159);
-        let pix2 = S::set1_ps(3.14159 * 2.0);
-
-        let mask = S::cmpge_ps(x, zero);
-        let adjust = S::blendv_ps(pi, zero, mask);
-        let mask = S::cmplt_ps(y, zero) & mask;
-        let adjust = S::blendv_ps(adjust, pix2, mask);
-
-        let r = S::sqrt_ps(x * x + y * y);
-        let theta = S::fast_atan_ps(y / x) + adjust;
-        (r, theta)
-    }
-}
-
 fn hsv_to_rgb<S: Simd>(h: S::Vf32, s: S::Vf32, v: S::Vf32) -> (S::Vf32, S::Vf32, S::Vf32) {
     unsafe {
         let six = S::set1_ps(6.0);
@@ -0,0 +1,192 @@
+use crate::genealogy::*;
+use crate::parser::*;
+use crate::pic::*;
+use std::fs;
+use std::sync::mpsc::*;
+
+/// Everything needed to pick a session back up where it was left. A saved
+/// session looks like:
+///
+/// ( Session
+///     ( Generation 3 )
+///     ( Seed 8815347390466042093 )
+///     ( Draws 2 )
+///     ( PopulationSeed 42 )
+///     ( Genealogy ( Record 0 0 Random ( Parents ) ) ... )
+///     ( Individual ( Id 50 ) ( Rating 1 )
+///         ( Mono ( Sin X ) ( Coord Polar ) ) ) )
+pub struct Session {
+    pub generation: usize,
+    /// What the rng was seeded with, a loaded session seeds it again and skips
+    /// draws numbers so it carries on with the ones the saved one would have
+    pub seed: u64,
+    pub draws: u64,
+    /// What the population was made or bred from, see individual_seed
+    pub population_seed: u64,
+    pub pics: Vec<Pic>,
+    pub ratings: Vec<f32>,
+    /// Genealogy id of each pic
+    pub ids: Vec<u64>,
+    pub genealogy: Genealogy,
+}
+
+impl Session {
+    pub fn to_lisp(&self) -> String {
+        let mut lisp = format!(
+            "( Session\n( Generation {} )\n( Seed {} )\n( Draws {} )\n( PopulationSeed {} )",
+            self.generation, self.seed, self.draws, self.population_seed
+        );
+        lisp += &format!("\n{}", self.genealogy.to_lisp());
+        for (i, pic) in self.pics.iter().enumerate() {
+            lisp += &format!(
+                "\n( Individual ( Id {} ) ( Rating {} )\n{} )",
+                self.ids[i],
+                self.ratings[i],
+                pic.to_lisp()
+            );
+        }
+        lisp + " )\n"
+    }
+}
+
+pub fn save_session(path: &str, session: &Session) -> Result<(), String> {
+    fs::write(path, session.to_lisp())
+        .map_err(|e| format!("Unable to write session {}: {}", path, e))
+}
+
+pub fn load_session(path: &str) -> Result<Session, String> {
+    let code =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read session {}: {}", path, e))?;
+    lisp_to_session(&code)
+}
+
+pub fn lisp_to_session(code: &str) -> Result<Session, String> {
+    let (sender, receiver) = channel();
+    Lexer::begin_lexing(code, sender);
+    parse_session(&receiver)
+}
+
+fn parse_individual(receiver: &Receiver<Token>) -> Result<(Pic, f32, u64), String> {
+    expect_open_paren(receiver)?;
+    expect_operation("id", receiver)?;
+    let id = expect_integer(receiver)?;
+    expect_close_paren(receiver)?;
+
+    expect_open_paren(receiver)?;
+    expect_operation("rating", receiver)?;
+    let rating = expect_constant(receiver)?;
+    expect_close_paren(receiver)?;
+
+    let pic = parse_pic(receiver)?;
+    expect_close_paren(receiver)?;
+    Ok((pic, rating, id))
+}
+
+pub fn parse_session(receiver: &Receiver<Token>) -> Result<Session, String> {
+    let mut session = Session {
+        generation: 0,
+        seed: 0,
+        draws: 0,
+        population_seed: 0,
+        pics: Vec::new(),
+        ratings: Vec::new(),
+        ids: Vec::new(),
+        genealogy: Genealogy::new(),
+    };
+    expect_open_paren(receiver)?;
+    expect_operation("session", receiver)?;
+    loop {
+        let token = receiver.recv().map_err(|_| "Unexpected end of file")?;
+        match token {
+            Token::CloseParen(_) => break,
+            Token::OpenParen(_) => (),
+            _ => {
+                return Err(format!(
+                    "Expected '(' on line {}",
+                    extract_line_number(&token)
+                ))
+            }
+        }
+        let entry = expect_operations(
+            vec![
+                "generation",
+                "seed",
+                "draws",
+                "populationseed",
+                "genealogy",
+                "individual",
+            ],
+            receiver,
+        )?;
+        match &entry.to_lowercase()[..] {
+            "generation" => {
+                session.generation = expect_integer(receiver)? as usize;
+                expect_close_paren(receiver)?;
+            }
+            "seed" => {
+                session.seed = expect_integer(receiver)?;
+                expect_close_paren(receiver)?;
+            }
+            "draws" => {
+                session.draws = expect_integer(receiver)?;
+                expect_close_paren(receiver)?;
+            }
+            "populationseed" => {
+                session.population_seed = expect_integer(receiver)?;
+                expect_close_paren(receiver)?;
+            }
+            "genealogy" => session.genealogy = parse_records(receiver)?,
+            _ => {
+                let (pic, rating, id) = parse_individual(receiver)?;
+                session.pics.push(pic);
+                session.ratings.push(rating);
+                session.ids.push(id);
+            }
+        }
+    }
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_round_trip() {
+        let mut genealogy = Genealogy::new();
+        let a = genealogy.add(Vec::new(), Origin::Random, 0);
+        let b = genealogy.add(Vec::new(), Origin::Random, 0);
+        let c = genealogy.add(vec![a, b], Origin::Crossover, 1);
+        let pics = vec![
+            lisp_to_pic("( Mono ( Sin X ) )".to_string()).unwrap(),
+            lisp_to_pic("( Grayscale ( * X Y ) ( Coord Cartesian ) )".to_string()).unwrap(),
+        ];
+        let session = Session {
+            generation: 1,
+            seed: 8815347390466042093,
+            draws: 2,
+            population_seed: 42,
+            pics,
+            ratings: vec![0.5, 0.0],
+            ids: vec![c, a],
+            genealogy,
+        };
+        let lisp = session.to_lisp();
+        let loaded = lisp_to_session(&lisp).unwrap();
+        assert_eq!(loaded.generation, 1);
+        assert_eq!(loaded.seed, 8815347390466042093);
+        assert_eq!(loaded.draws, 2);
+        assert_eq!(loaded.population_seed, 42);
+        assert_eq!(loaded.ratings, vec![0.5, 0.0]);
+        assert_eq!(loaded.ids, vec![c, a]);
+        assert_eq!(loaded.genealogy.get(c).unwrap().parents, vec![a, b]);
+        assert_eq!(loaded.to_lisp(), lisp);
+    }
+
+    #[test]
+    fn sessions_without_draws_start_at_the_seed() {
+        let lisp = "( Session ( Generation 0 ) ( Seed 7 ) ( PopulationSeed 7 ) ( Genealogy ) )";
+        let loaded = lisp_to_session(lisp).unwrap();
+        assert_eq!((loaded.seed, loaded.draws), (7, 0));
+    }
+}
@@ -1,47 +1,55 @@
 use crate::actual_picture::*;
 use crate::apt::*;
+use crate::noise::*;
+use crate::operator::*;
+use crate::transform::*;
 use simdeez::*;
-use simdnoise::*;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
-pub const SIMPLEX_MULTIPLIER: f32 = 7.35;
-pub const SIMPLEX_OFFSET: f32 = 0.028;
-pub const CELL1_MULTUPLIER: f32 = 1.661291;
-pub const CELL1_OFFSET: f32 = 1.0;
 use Instruction::*;
 
+/// Where a frame is in time. T and T2 always sit on the unit circle, linear
+/// time goes half way round it and looping time all the way, so the last frame
+/// leads back into the first
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Time {
+    pub t: f32,
+    pub t2: f32,
+}
+
+impl Time {
+    /// t runs from -1 to 1 over a video, T2 rises from 0 to 1 half way and
+    /// falls back to 0
+    pub fn linear(t: f32) -> Time {
+        Time {
+            t,
+            t2: (1.0 - t * t).max(0.0).sqrt(),
+        }
+    }
+
+    /// phase runs from 0 to 1 over a video, a phase of 1 is the same as 0
+    pub fn looping(phase: f32) -> Time {
+        let angle = phase * 2.0 * std::f32::consts::PI;
+        Time {
+            t: angle.cos(),
+            t2: angle.sin(),
+        }
+    }
+}
+
 pub enum Instruction<S: Simd> {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    FBM,
-    Ridge,
-    Turbulence,
-    Cell1,
-    Cell2,
-    Sqrt,
-    Sin,
-    Atan,
-    Atan2,
-    Tan,
-    Log,
-    Abs,
-    Floor,
-    Ceil,
-    Clamp,
-    Wrap,
-    Square,
-    Max,
-    Min,
-    Mod,
-    Mandlebrot,
+    Op(Operator),
+    Noise(Operator, NoiseParams),
+    /// Moves the coordinates for everything up to the matching EndTransform
+    Transform(Operator),
+    EndTransform,
     Picture(String),
     Constant(S::Vf32),
     X,
     Y,
     T,
+    T2,
 }
 
 pub struct StackMachine<S: Simd> {
@@ -51,44 +59,35 @@ pub struct StackMachine<S: Simd> {
 impl<S: Simd> StackMachine<S> {
     pub fn get_instruction(node: &APTNode) -> Instruction<S> {
         match node {
-            APTNode::Add(_) => Add,
-            APTNode::Sub(_) => Sub,
-            APTNode::Mul(_) => Mul,
-            APTNode::Div(_) => Div,
-            APTNode::FBM(_) => FBM,
-            APTNode::Ridge(_) => Ridge,
-            APTNode::Turbulence(_) => Turbulence,
-            APTNode::Cell1(_) => Cell1,
-            APTNode::Cell2(_) => Cell2,
-            APTNode::Sqrt(_) => Sqrt,
-            APTNode::Sin(_) => Sin,
-            APTNode::Atan(_) => Atan,
-            APTNode::Atan2(_) => Atan2,
-            APTNode::Tan(_) => Tan,
-            APTNode::Log(_) => Log,
-            APTNode::Abs(_) => Abs,
-            APTNode::Floor(_) => Floor,
-            APTNode::Ceil(_) => Ceil,
-            APTNode::Clamp(_) => Clamp,
-            APTNode::Wrap(_) => Wrap,
-            APTNode::Square(_) => Square,
-            APTNode::Max(_) => Max,
-            APTNode::Min(_) => Min,
-            APTNode::Mod(_) => Mod,
-            APTNode::Mandlebrot(_) => Mandlebrot,
+            APTNode::Op(op, _) => Op(*op),
+            APTNode::Noise(op, params, _) => Noise(*op, *params),
             APTNode::Picture(name, _) => Picture(name.to_string()),
             APTNode::Constant(v) => Constant(unsafe { S::set1_ps(*v) }),
             APTNode::X => X,
             APTNode::Y => Y,
             APTNode::T => T,
+            APTNode::T2 => T2,
             APTNode::Empty => panic!("got empty building stack machine"),
         }
     }
 
     fn build_helper(&mut self, node: &APTNode) {
+        match node {
+            APTNode::Op(op, children) if op.is_transform() => {
+                let (body, args) = children.split_last().unwrap();
+                for arg in args {
+                    self.build_helper(arg);
+                }
+                self.instructions.push(Transform(*op));
+                self.build_helper(body);
+                self.instructions.push(EndTransform);
+                return;
+            }
+            _ => (),
+        }
         match node.get_children() {
             Some(children) => {
-                for child in children.iter().rev() {
+                for child in children {
                     self.build_helper(child);
                 }
             }
@@ -129,199 +128,50 @@ impl<S: Simd> StackMachine<S> {
         x: S::Vf32,
         y: S::Vf32,
         t: S::Vf32,
+        t2: S::Vf32,
     ) -> S::Vf32 {
         unsafe {
             let mut sp = 0;
+            let (mut x, mut y) = (x, y);
             for ins in &self.instructions {
                 match ins {
-                    Add => {
-                        sp -= 1;
-                        stack[sp - 1] = stack[sp] + stack[sp - 1];
-                    }
-                    Sub => {
-                        sp -= 1;
-                        stack[sp - 1] = stack[sp] - stack[sp - 1];
-                    }
-                    Mul => {
-                        sp -= 1;
-                        stack[sp - 1] = stack[sp] * stack[sp - 1];
-                    }
-                    Div => {
-                        sp -= 1;
-                        stack[sp - 1] = StackMachine::<S>::deal_with_nan(stack[sp] / stack[sp - 1]);
-                    }
-                    FBM => {
-                        sp -= 5;
-                        let xfreq = stack[sp - 1] * S::set1_ps(15.0);
-                        let yfreq = stack[sp + 4] * S::set1_ps(15.0);
-                        let lacunarity = stack[sp + 2] * S::set1_ps(5.0);
-                        let gain = stack[sp + 3] * S::set1_ps(0.5);
-                        let octaves = 3;
-                        stack[sp - 1] = simdnoise::simplex::fbm_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
-                            lacunarity,
-                            gain,
-                            octaves,
-                            3,
-                        ); //* S::set1_ps(SIMPLEX_MULTIPLIER)
-                           //- S::set1_ps(SIMPLEX_OFFSET); //todo clamp between -1 and 1??
-                    }
-                    Ridge => {
-                        sp -= 5;
-                        let xfreq = stack[sp - 1] * S::set1_ps(15.0);
-                        let yfreq = stack[sp + 4] * S::set1_ps(15.0);
-                        let lacunarity = stack[sp + 2] * S::set1_ps(5.0);
-                        let gain = stack[sp + 3] * S::set1_ps(0.5);
-                        let octaves = 3;
-                        stack[sp - 1] = simdnoise::simplex::ridge_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
-                            lacunarity,
-                            gain,
-                            octaves,
-                            3,
-                        ); // S::set1_ps(SIMPLEX_OFFSET)
-                           //- S::set1_ps(SIMPLEX_OFFSET); //todo clamp between -1 and 1??
-                    }
-                    Turbulence => {
-                        sp -= 5;
-                        let xfreq = stack[sp - 1] * S::set1_ps(15.0);
-                        let yfreq = stack[sp + 4] * S::set1_ps(15.0);
-                        let lacunarity = stack[sp + 2] * S::set1_ps(5.0);
-                        let gain = stack[sp + 3] * S::set1_ps(0.5);
-                        let octaves = 3;
-                        stack[sp - 1] = simdnoise::simplex::turbulence_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
-                            lacunarity,
-                            gain,
-                            octaves,
-                            3,
-                        ); // * S::set1_ps(SIMPLEX_MULTIPLIER)
-                           //- S::set1_ps(SIMPLEX_OFFSET); //todo clamp between -1 and 1?? \
-                    }
-                    Cell1 => {
-                        sp -= 4;
-                        let xfreq = stack[sp - 1] * S::set1_ps(4.0);
-                        let yfreq = stack[sp + 3] * S::set1_ps(4.0);
-                        let jitter = stack[sp + 2] * S::set1_ps(0.5);
-                        stack[sp - 1] = simdnoise::cellular::cellular_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
-                            CellDistanceFunction::Euclidean,
-                            CellReturnType::Distance,
-                            jitter,
-                            1,
-                        ); //* S::set1_ps(CELL1_MULTUPLIER)
-                           //- S::set1_ps(CELL1_OFFSET); //todo clamp between -1 and 1?? \
-                    }
-                    Cell2 => {
-                        sp -= 4;
-                        let xfreq = stack[sp - 1] * S::set1_ps(4.0);
-                        let yfreq = stack[sp + 3] * S::set1_ps(4.0);
-                        let jitter = stack[sp + 2] * S::set1_ps(0.5);
-                        stack[sp - 1] = simdnoise::cellular::cellular_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
-                            CellDistanceFunction::Euclidean,
-                            CellReturnType::CellValue,
-                            jitter,
-                            1,
-                        );
-                    }
-                    Sqrt => {
-                        let v = stack[sp - 1];
-                        let positive = S::sqrt_ps(v);
-                        let negative = S::mul_ps(S::set1_ps(-1.0), S::sqrt_ps(S::abs_ps(v)));
-                        let mask = S::cmpge_ps(v, S::setzero_ps());
-                        stack[sp - 1] = S::blendv_ps(negative, positive, mask);
-                    }
-                    Sin => {
-                        stack[sp - 1] = S::fast_sin_ps(stack[sp - 1] * S::set1_ps(3.14159));
-                    }
-                    Atan => {
-                        stack[sp - 1] = S::fast_atan_ps(stack[sp - 1] * S::set1_ps(4.0))
-                            * S::set1_ps(0.666666666);
-                    }
-                    Atan2 => {
-                        sp -= 1;
-                        let x = stack[sp - 1];
-                        let y = stack[sp] * S::set1_ps(4.0);
-                        stack[sp - 1] = S::fast_atan2_ps(y, x) * S::set1_ps(0.318309);
-                    }
-                    Tan => {
-                        stack[sp - 1] = S::fast_tan_ps(stack[sp - 1] * S::set1_ps(1.57079632679));
-                    }
-                    Log => {
-                        let v = stack[sp - 1] * S::set1_ps(4.0);
-                        let positive = S::fast_ln_ps(v);
-                        let negative = S::mul_ps(S::set1_ps(-1.0), S::fast_ln_ps(S::abs_ps(v)));
-                        let mask = S::cmpge_ps(v, S::setzero_ps());
-                        stack[sp - 1] =
-                            S::blendv_ps(negative, positive, mask) * S::set1_ps(0.367879);
-                    }
-                    Abs => {
-                        stack[sp - 1] = S::abs_ps(stack[sp - 1]);
-                    }
-                    Floor => {
-                        stack[sp - 1] = S::fast_floor_ps(stack[sp - 1]);
-                    }
-                    Ceil => {
-                        stack[sp - 1] = S::fast_ceil_ps(stack[sp - 1]);
-                    }
-                    Clamp => {
-                        let mut v = stack[sp - 1];
-                        for i in 0..S::VF32_WIDTH {
-                            if v[i] > 1.0 {
-                                v[i] = 1.0
-                            } else if v[i] < -1.0 {
-                                v[i] = -1.0
-                            }
-                        }
+                    Op(op) => {
+                        // children are pushed in order, so the args are the
+                        // top arity entries of the stack, first child lowest
+                        let arity = op.arity();
+                        sp -= arity - 1;
+                        let v = op.simd::<S>(&stack[sp - 1..sp - 1 + arity]);
                         stack[sp - 1] = v;
                     }
-                    Wrap => {
-                        let mut v = stack[sp - 1];
-                        for i in 0..S::VF32_WIDTH {
-                            if v[i] < -1.0 || v[i] > 1.0 {
-                                let t = (v[i] + 1.0) / 2.0;
-                                v[i] = -1.0 + 2.0 * (t - t.floor());
-                            }
-                        }
+                    Noise(op, params) => {
+                        let arity = op.arity();
+                        sp -= arity - 1;
+                        let v = noise_simd::<S>(*op, &stack[sp - 1..sp - 1 + arity], params);
                         stack[sp - 1] = v;
                     }
-                    Square => {
-                        let v = stack[sp - 1];
-                        stack[sp - 1] = v * v;
-                    }
-                    Max => {
-                        sp -= 1;
-                        stack[sp - 1] = S::max_ps(stack[sp - 1], stack[sp]);
-                    }
-                    Min => {
-                        sp -= 1;
-                        stack[sp - 1] = S::min_ps(stack[sp - 1], stack[sp]);
-                    }
-                    Mod => {
-                        sp -= 1;
-                        let a = stack[sp - 1];
-                        let b = stack[sp];
-                        let mut r = S::setzero_ps();
-                        for i in 0..S::VF32_WIDTH {
-                            r[i] = a[i] % b[i];
-                        }
-                        stack[sp - 1] = r;
+                    Transform(op) => {
+                        // the outer coordinates wait on the stack in place of
+                        // the args until the body is done
+                        let arity = op.arity() - 1;
+                        sp -= arity;
+                        let (tx, ty) = transform_simd::<S>(*op, &stack[sp..sp + arity], x, y);
+                        stack[sp] = x;
+                        stack[sp + 1] = y;
+                        sp += 2;
+                        x = tx;
+                        y = ty;
                     }
-                    Mandlebrot => {
-                        sp -= 1;
-                        //todo do
+                    EndTransform => {
+                        sp -= 2;
+                        x = stack[sp - 1];
+                        y = stack[sp];
+                        stack[sp - 1] = stack[sp + 1];
                     }
                     Picture(name) => {
                         sp -= 1;
 
-                        let y = stack[sp - 1];
-                        let x = stack[sp];
+                        let x = stack[sp - 1];
+                        let y = stack[sp];
 
                         let picture = &pics[name];
                         let w = S::set1_epi32(picture.w as i32);
@@ -360,6 +210,10 @@ impl<S: Simd> StackMachine<S> {
                         stack[sp] = t;
                         sp += 1;
                     }
+                    T2 => {
+                        stack[sp] = t2;
+                        sp += 1;
+                    }
                 }
             }
             stack[sp - 1]
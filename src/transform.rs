@@ -0,0 +1,83 @@
+use crate::operator::*;
+use simdeez::*;
+use std::f32::consts::PI;
+
+// Tile and Kaleidoscope turn an arg from -1 to 1 into a count up to these
+const MAX_TILES: f32 = 8.0;
+const MAX_SEGMENTS: f32 = 12.0;
+
+/// Operators that evaluate their last child with moved X and Y instead of
+/// computing a value. The args before the body are evaluated with the outer
+/// coordinates, then the stack machine swaps in the coordinates from
+/// transform_simd for the body
+pub const TRANSFORM_OPERATORS: &[Operator] = &[
+    Operator::Rotate,
+    Operator::Scale,
+    Operator::Translate,
+    Operator::MirrorX,
+    Operator::MirrorY,
+    Operator::Tile,
+    Operator::Kaleidoscope,
+];
+
+impl Operator {
+    pub fn is_transform(self) -> bool {
+        TRANSFORM_OPERATORS.contains(&self)
+    }
+}
+
+// Count from an arg, at least min and growing with the size of the arg
+#[inline(always)]
+unsafe fn count<S: Simd>(a: S::Vf32, min: f32, max: f32) -> S::Vf32 {
+    S::set1_ps(min) + S::fast_floor_ps(S::abs_ps(a) * S::set1_ps(max - min + 0.999))
+}
+
+/// Where the body of a transform looks, args are the ones before the body
+#[inline(always)]
+pub unsafe fn transform_simd<S: Simd>(
+    op: Operator,
+    a: &[S::Vf32],
+    x: S::Vf32,
+    y: S::Vf32,
+) -> (S::Vf32, S::Vf32) {
+    match op {
+        // ( Rotate angle body ), an angle of 1 is half a turn
+        Operator::Rotate => {
+            let angle = a[0] * S::set1_ps(PI);
+            let (cos, sin) = (S::fast_cos_ps(angle), S::fast_sin_ps(angle));
+            (x * cos + y * sin, y * cos - x * sin)
+        }
+        // ( Scale amount body ), an amount of 1 shrinks the body 4 times, -1 grows it
+        Operator::Scale => {
+            let factor = S::fast_exp_ps(a[0] * S::set1_ps(4.0f32.ln()));
+            (x * factor, y * factor)
+        }
+        // ( Translate dx dy body )
+        Operator::Translate => (x - a[0], y - a[1]),
+        // ( MirrorX body ), the right half reflected onto the left
+        Operator::MirrorX => (S::abs_ps(x), y),
+        // ( MirrorY body )
+        Operator::MirrorY => (x, S::abs_ps(y)),
+        // ( Tile columns rows body ), up to 8 by 8 copies of the body
+        Operator::Tile => {
+            let tile = |v: S::Vf32, n: S::Vf32| {
+                let t = (v + S::set1_ps(1.0)) * S::set1_ps(0.5) * n;
+                (t - S::fast_floor_ps(t)) * S::set1_ps(2.0) - S::set1_ps(1.0)
+            };
+            (
+                tile(x, count::<S>(a[0], 1.0, MAX_TILES)),
+                tile(y, count::<S>(a[1], 1.0, MAX_TILES)),
+            )
+        }
+        // ( Kaleidoscope segments body ), 2 to 12 mirrored wedges around the middle
+        Operator::Kaleidoscope => {
+            let segment = S::set1_ps(2.0 * PI) / count::<S>(a[0], 2.0, MAX_SEGMENTS);
+            let r = S::sqrt_ps(x * x + y * y);
+            let angle = S::fast_atan2_ps(y, x) + S::set1_ps(PI);
+            let angle = angle - S::fast_floor_ps(angle / segment) * segment;
+            let angle = S::abs_ps(angle - segment * S::set1_ps(0.5));
+            (r * S::fast_cos_ps(angle), r * S::fast_sin_ps(angle))
+        }
+        _ => (x, y),
+    }
+}
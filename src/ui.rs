@@ -66,13 +66,37 @@ impl Button {
         }
     }
 
+    pub fn middle_clicked(&self, ctx: &mut Context, mouse_state: &MouseState) -> bool {
+        match mouse_state {
+            MouseState::Up(button_state) => {
+                button_state.which_button == MouseButton::Middle
+                    && self
+                        .pixel_rect(ctx)
+                        .contains(na::Point2::new(button_state.x, button_state.y))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn draw_highlight(&self, ctx: &mut Context) {
+        let pixel_rect = self.pixel_rect(ctx);
+        if let Ok(border) = Mesh::new_rectangle(ctx, DrawMode::stroke(4.0), pixel_rect, WHITE) {
+            let _ = draw(ctx, &border, DrawParam::new());
+        }
+    }
+
     pub fn draw(&self, ctx: &mut Context) {
+        self.draw_image(ctx, &self.img);
+    }
+
+    /// Draws img stretched over the button instead of its own image
+    pub fn draw_image(&self, ctx: &mut Context, img: &Image) {
         let pixel_rect = self.pixel_rect(ctx);
-        let x_scale = pixel_rect.w / self.img.width() as f32;
-        let y_scale = pixel_rect.h / self.img.height() as f32;
+        let x_scale = pixel_rect.w / img.width() as f32;
+        let y_scale = pixel_rect.h / img.height() as f32;
         let params = DrawParam::new()
             .dest(na::Point2::new(pixel_rect.x, pixel_rect.y))
             .scale(na::Vector2::new(x_scale, y_scale));
-        let _ = draw(ctx, &self.img, params);
+        let _ = draw(ctx, img, params);
     }
 }
@@ -0,0 +1,179 @@
+use crate::actual_picture::*;
+use crate::antialias::*;
+use crate::coordinates::*;
+use crate::pic::*;
+use gif::SetParameter;
+use simdeez::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VideoFormat {
+    /// A directory of frame0000.png, frame0001.png, ...
+    PngSequence,
+    Gif,
+    Apng,
+}
+
+impl VideoFormat {
+    pub fn from_name(s: &str) -> Option<VideoFormat> {
+        match &s.to_lowercase()[..] {
+            "png" => Some(VideoFormat::PngSequence),
+            "gif" => Some(VideoFormat::Gif),
+            "apng" => Some(VideoFormat::Apng),
+            _ => None,
+        }
+    }
+
+    /// Where the GUI writes videos of this format
+    pub fn default_path(self) -> &'static str {
+        match self {
+            VideoFormat::PngSequence => "frames",
+            VideoFormat::Gif => "video.gif",
+            VideoFormat::Apng => "video.png",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct VideoSettings {
+    pub width: usize,
+    pub height: usize,
+    pub fps: u16,
+    /// Milliseconds, like get_video
+    pub duration: f32,
+    pub format: VideoFormat,
+    /// Run time round a circle instead of from -1 to 1, so the end meets the start
+    pub looping: bool,
+    /// The part of the plane to render, stills use it too
+    pub viewport: Viewport,
+    /// Supersampling for every frame, stills use it too
+    pub antialias: Antialias,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            width: 512,
+            height: 512,
+            fps: 30,
+            duration: 5000.0,
+            format: VideoFormat::Gif,
+            looping: false,
+            viewport: Viewport::default(),
+            antialias: Antialias::Off,
+        }
+    }
+}
+
+/// Renders pic over t and writes it to path in the chosen format. Frames go
+/// straight to the encoder as they are made, so only a few are in memory at once
+pub fn export_video<S: Simd>(
+    pic: &Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    settings: &VideoSettings,
+    path: &str,
+) -> Result<(), String> {
+    let (w, h, fps) = (settings.width, settings.height, settings.fps);
+    let (d, looping, viewport) = (settings.duration, settings.looping, &settings.viewport);
+    let antialias = settings.antialias;
+    match settings.format {
+        VideoFormat::PngSequence => {
+            create_frame_dir(path)?;
+            // each frame is its own file, so they can be written in any order
+            pic.stream_video_unordered::<S, _>(
+                pictures,
+                w,
+                h,
+                viewport,
+                antialias,
+                fps,
+                d,
+                looping,
+                |i, frame| write_png_frame(frame, w, h, path, i),
+            )
+        }
+        VideoFormat::Gif => {
+            let mut encoder = gif_encoder(w, h, path)?;
+            let sink = |_, frame: &[u8]| write_gif_frame(&mut encoder, frame, w, h, fps);
+            pic.stream_video::<S, _>(pictures, w, h, viewport, antialias, fps, d, looping, sink)
+        }
+        VideoFormat::Apng => {
+            let mut writer = apng_writer(w, h, fps, frame_count(fps, d), path)?;
+            let sink = |_, frame: &[u8]| writer.write_image_data(frame).map_err(|e| e.to_string());
+            pic.stream_video::<S, _>(pictures, w, h, viewport, antialias, fps, d, looping, sink)
+        }
+    }
+}
+
+fn create_frame_dir(dir: &str) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Unable to create {}: {}", dir, e))
+}
+
+/// Writes frame i of a png sequence as dir/frame0000.png
+pub fn write_png_frame(
+    frame: &[u8],
+    w: usize,
+    h: usize,
+    dir: &str,
+    i: usize,
+) -> Result<(), String> {
+    let path = Path::new(dir).join(format!("frame{:04}.png", i));
+    let file =
+        File::create(&path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(frame).map_err(|e| e.to_string())
+}
+
+type GifEncoder = gif::Encoder<BufWriter<File>>;
+
+fn gif_encoder(w: usize, h: usize, path: &str) -> Result<GifEncoder, String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), w as u16, h as u16, &[])
+        .map_err(|e| e.to_string())?;
+    encoder
+        .set(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+    Ok(encoder)
+}
+
+fn write_gif_frame(
+    encoder: &mut GifEncoder,
+    frame: &[u8],
+    w: usize,
+    h: usize,
+    fps: u16,
+) -> Result<(), String> {
+    let mut pixels = frame.to_vec();
+    let mut gif_frame = gif::Frame::from_rgba_speed(w as u16, h as u16, &mut pixels, 10);
+    // gif delays are in hundredths of a second
+    gif_frame.delay = (100.0 / fps as f32).round() as u16;
+    encoder.write_frame(&gif_frame).map_err(|e| e.to_string())
+}
+
+type ApngWriter = png::Writer<BufWriter<File>>;
+
+fn apng_writer(
+    w: usize,
+    h: usize,
+    fps: u16,
+    frame_count: usize,
+    path: &str,
+) -> Result<ApngWriter, String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // 0 plays loops forever
+    encoder
+        .set_animated(frame_count as u32, 0)
+        .map_err(|e| e.to_string())?;
+    encoder.set_frame_delay(1, fps).map_err(|e| e.to_string())?;
+    encoder.write_header().map_err(|e| e.to_string())
+}
@@ -0,0 +1,11 @@
+( Generation
+    ( Weight FBM 4 )
+    ( Weight Ridge 4 )
+    ( Weight Turbulence 4 )
+    ( Weight Cell1 3 )
+    ( Weight Cell2 3 )
+    ( Disable Floor )
+    ( Disable Ceil )
+    ( Disable Mod )
+    ( Leaf Constant 2 )
+    ( ConstantRange -1 1 ) )